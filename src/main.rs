@@ -2,20 +2,25 @@ use rand::distributions::{IndependentSample, Weighted, WeightedChoice};
 use rand::Rng;
 use serde_derive::{Deserialize, Serialize};
 use std::cmp;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 use tcod::colors::*;
 use tcod::console::*;
 use tcod::input::{self, Event, Key, KeyCode::*, Mouse};
+use tcod::line::Line;
 use tcod::map::{FovAlgorithm, Map as FovMap};
 
 const CHARACTER_SCREEN_WIDTH: i32 = 30;
 const SCREEN_WIDTH: i32 = 80;
 const SCREEN_HEIGHT: i32 = 50;
 
-const MAP_WIDTH: i32 = 80;
-const MAP_HEIGHT: i32 = 43;
+const MAP_WIDTH: i32 = 120;
+const MAP_HEIGHT: i32 = 70;
+
+const CAMERA_WIDTH: i32 = SCREEN_WIDTH;
+const CAMERA_HEIGHT: i32 = SCREEN_HEIGHT - PANEL_HEIGHT;
 
 const COLOR_DARK_WALL: Color = Color { r: 0, g: 0, b: 100 };
 const COLOR_LIGHT_WALL: Color = Color {
@@ -33,16 +38,46 @@ const COLOR_LIGHT_GROUND: Color = Color {
     g: 180,
     b: 50,
 };
+const COLOR_DOOR: Color = LIGHT_SEPIA;
+const COLOR_LOCKED_DOOR: Color = GOLD;
+const COLOR_DARK_DOOR: Color = DARKER_SEPIA;
+const COLOR_LIGHT_SHALLOW_WATER: Color = AZURE;
+const COLOR_DARK_SHALLOW_WATER: Color = DARKER_AZURE;
+const COLOR_LIGHT_DEEP_WATER: Color = DARK_BLUE;
+const COLOR_DARK_DEEP_WATER: Color = DARKEST_BLUE;
+const COLOR_LIGHT_LAVA: Color = FLAME;
+const COLOR_DARK_LAVA: Color = DARKER_FLAME;
+const COLOR_LIGHT_CHASM: Color = DARKEST_GREY;
+const COLOR_DARK_CHASM: Color = BLACK;
+const COLOR_LIGHT_RUBBLE: Color = SEPIA;
+const COLOR_DARK_RUBBLE: Color = DARKER_SEPIA;
+const COLOR_CURSOR: Color = LIGHT_YELLOW;
 
 const LIMIT_FPS: i32 = 20;
 
 const ROOM_MAX_SIZE: i32 = 10;
 const ROOM_MIN_SIZE: i32 = 6;
-const MAX_ROOMS: i32 = 30;
+const MAX_ROOMS: i32 = 60;
+
+const DOOR_CHANCE: i32 = 60;
+const MONSTER_TORCH_CHANCE: i32 = 25;
+const ORC_WEAPON_CHANCE: i32 = 30;
+const ORC_SHIELD_CHANCE: i32 = 20;
+const TROLL_HELMET_CHANCE: i32 = 25;
+const TERRAIN_CHANCE: i32 = 30;
 
 const FOV_ALGO: FovAlgorithm = FovAlgorithm::Basic;
 const FOV_LIGHT_WALLS: bool = true;
+const FOV_RADIUS: i32 = 0;
 const TORCH_RADIUS: i32 = 10;
+const TORCH_COLOR: Color = Color {
+    r: 255,
+    g: 230,
+    b: 180,
+};
+const WALL_TORCH_RADIUS: i32 = 6;
+const MONSTER_TORCH_RADIUS: i32 = 4;
+const COLOR_ROOM_LIGHT: Color = WHITE;
 
 const PLAYER: usize = 0;
 
@@ -53,6 +88,7 @@ const PANEL_Y: i32 = SCREEN_HEIGHT - PANEL_HEIGHT;
 const MSG_X: i32 = BAR_WIDTH + 2;
 const MSG_WIDTH: i32 = SCREEN_WIDTH - BAR_WIDTH - 2;
 const MSG_HEIGHT: usize = PANEL_HEIGHT as usize - 1;
+const MSG_HISTORY_SIZE: usize = 1000;
 
 const HEAL_AMOUNT: i32 = 40;
 
@@ -103,18 +139,125 @@ fn level_up(objects: &mut [Object], game: &mut Game, tcod: &mut Tcod) {
     }
 }
 
-type Messages = Vec<(String, Color)>;
+#[derive(Serialize, Deserialize)]
+struct Message {
+    text: String,
+    color: Color,
+    #[serde(default = "default_count")]
+    count: u32,
+    #[serde(default)]
+    turn: u32,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+impl Message {
+    fn text(&self) -> String {
+        if self.count > 1 {
+            format!("{} (x{})", self.text, self.count)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(from = "SavedMessages")]
+struct Messages {
+    messages: Vec<Message>,
+    turn: u32,
+    #[serde(skip)]
+    danger: bool,
+}
+
+const DANGER_COLORS: &[Color] = &[RED, ORANGE];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedMessages {
+    Grouped {
+        messages: Vec<Message>,
+        #[serde(default)]
+        turn: u32,
+    },
+    Plain(Vec<(String, Color)>),
+}
+
+impl From<SavedMessages> for Messages {
+    fn from(saved: SavedMessages) -> Self {
+        match saved {
+            SavedMessages::Grouped { messages, turn } => Messages {
+                messages,
+                turn,
+                danger: false,
+            },
+            SavedMessages::Plain(messages) => Messages {
+                messages: messages
+                    .into_iter()
+                    .map(|(text, color)| Message {
+                        text,
+                        color,
+                        count: 1,
+                        turn: 0,
+                    })
+                    .collect(),
+                turn: 0,
+                danger: false,
+            },
+        }
+    }
+}
+
+impl Messages {
+    fn new() -> Self {
+        Messages {
+            messages: vec![],
+            turn: 0,
+            danger: false,
+        }
+    }
+
+    fn next_turn(&mut self) {
+        self.turn += 1;
+    }
+
+    fn take_danger(&mut self) -> bool {
+        std::mem::replace(&mut self.danger, false)
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = &Message> {
+        self.messages.iter()
+    }
+}
 
 trait MessageLog {
     fn add<T: Into<String>>(&mut self, message: T, color: Color);
 }
 
-impl MessageLog for Vec<(String, Color)> {
+impl MessageLog for Messages {
     fn add<T: Into<String>>(&mut self, message: T, color: Color) {
-        if self.len() == MSG_HEIGHT {
-            self.remove(0);
+        let text = message.into();
+        if DANGER_COLORS.contains(&color) {
+            self.danger = true;
         }
-        self.push((message.into(), color));
+        if let Some(last) = self.messages.last_mut() {
+            if last.text == text && last.color == color {
+                last.count += 1;
+                last.turn = self.turn;
+                return;
+            }
+        }
+        if self.messages.len() == MSG_HISTORY_SIZE {
+            self.messages.remove(0);
+        }
+        self.messages.push(Message {
+            text,
+            color,
+            count: 1,
+            turn: self.turn,
+        });
     }
 }
 
@@ -139,14 +282,155 @@ fn monster_death(monster: &mut Object, game: &mut Game) {
         ),
         ORANGE,
     );
+    let (x, y) = monster.pos();
+    let terrain = game.map[x as usize][y as usize].terrain;
+    if terrain.swallows_items() {
+        monster.inventory.clear();
+    }
+    for item in monster.inventory.iter_mut() {
+        if let Some(ref mut equipment) = item.equipment {
+            equipment.equipped = false;
+            equipment.off_hand = false;
+        }
+        item.set_pos(x, y);
+    }
+    let (loot_chance, mut loot) = match monster.monster {
+        Some(kind) => loot_table(kind, game.dungeon_level),
+        None => (0, vec![]),
+    };
+    if rand::thread_rng().gen_range(0, 100) < loot_chance {
+        let item = WeightedChoice::new(&mut loot).ind_sample(&mut rand::thread_rng());
+        let tiles: Vec<_> = std::iter::once((x, y))
+            .chain(adjacent_tiles(x, y))
+            .filter(|&(x, y)| {
+                let tile = &game.map[x as usize][y as usize];
+                !tile.blocked && !tile.terrain.swallows_items()
+            })
+            .collect();
+        if !tiles.is_empty() {
+            let (loot_x, loot_y) = tiles[rand::thread_rng().gen_range(0, tiles.len())];
+            monster.inventory.push(create_item(item, loot_x, loot_y));
+        }
+    }
+    if !monster.inventory.is_empty() {
+        let names: Vec<_> = monster
+            .inventory
+            .iter()
+            .map(|item| item.counted_name(game))
+            .collect();
+        game.log.add(
+            format!("The {} drops {}.", monster.name, names.join(", ")),
+            WHITE,
+        );
+    }
     monster.char = '%';
     monster.color = DARK_RED;
     monster.blocks = false;
     monster.fighter = None;
     monster.ai = None;
+    monster.light = None;
     monster.name = format!("remains of {}", monster.name);
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Monster {
+    Orc,
+    Troll,
+}
+
+fn loot_table(monster: Monster, level: u32) -> (i32, Vec<Weighted<Item>>) {
+    match monster {
+        Monster::Orc => (
+            from_dungeon_level(
+                &[
+                    Transition {
+                        level: 1,
+                        value: 25,
+                    },
+                    Transition {
+                        level: 4,
+                        value: 35,
+                    },
+                ],
+                level,
+            ) as i32,
+            vec![
+                Weighted {
+                    weight: 60,
+                    item: Item::Heal,
+                },
+                Weighted {
+                    weight: from_dungeon_level(
+                        &[Transition {
+                            level: 2,
+                            value: 20,
+                        }],
+                        level,
+                    ),
+                    item: Item::Confuse,
+                },
+                Weighted {
+                    weight: from_dungeon_level(
+                        &[Transition {
+                            level: 4,
+                            value: 15,
+                        }],
+                        level,
+                    ),
+                    item: Item::Lightning,
+                },
+                Weighted {
+                    weight: 10,
+                    item: Item::Key,
+                },
+            ],
+        ),
+        Monster::Troll => (
+            from_dungeon_level(
+                &[
+                    Transition {
+                        level: 1,
+                        value: 50,
+                    },
+                    Transition {
+                        level: 6,
+                        value: 70,
+                    },
+                ],
+                level,
+            ) as i32,
+            vec![
+                Weighted {
+                    weight: 40,
+                    item: Item::Heal,
+                },
+                Weighted {
+                    weight: 20,
+                    item: Item::Lightning,
+                },
+                Weighted {
+                    weight: from_dungeon_level(
+                        &[Transition {
+                            level: 6,
+                            value: 20,
+                        }],
+                        level,
+                    ),
+                    item: Item::Fireball,
+                },
+                Weighted {
+                    weight: from_dungeon_level(&[Transition { level: 5, value: 5 }], level),
+                    item: Item::Ring,
+                },
+                Weighted {
+                    weight: from_dungeon_level(&[Transition { level: 6, value: 5 }], level),
+                    item: Item::BodyArmor,
+                },
+            ],
+        ),
+    }
+}
+
 impl DeathCallback {
     fn callback(self, object: &mut Object, game: &mut Game) {
         use DeathCallback::*;
@@ -175,6 +459,11 @@ enum Ai {
         previous_ai: Box<Ai>,
         num_turns: i32,
     },
+    Alerted {
+        x: i32,
+        y: i32,
+        num_turns: i32,
+    },
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
@@ -183,8 +472,125 @@ enum Item {
     Lightning,
     Confuse,
     Fireball,
+    Identify,
+    Recharge,
+    WandLightning,
+    WandConfuse,
+    WandDigging,
     Sword,
+    Greataxe,
+    Bow,
+    Crossbow,
+    Arrow,
+    Bolt,
     Shield,
+    Helmet,
+    BodyArmor,
+    Cloak,
+    Boots,
+    Amulet,
+    Ring,
+    Key,
+}
+
+impl Item {
+    fn all() -> &'static [Item] {
+        use Item::*;
+        &[
+            Heal,
+            Lightning,
+            Confuse,
+            Fireball,
+            Identify,
+            Recharge,
+            WandLightning,
+            WandConfuse,
+            WandDigging,
+            Sword,
+            Greataxe,
+            Bow,
+            Crossbow,
+            Arrow,
+            Bolt,
+            Shield,
+            Helmet,
+            BodyArmor,
+            Cloak,
+            Boots,
+            Amulet,
+            Ring,
+            Key,
+        ]
+    }
+
+    fn stackable(self) -> bool {
+        !matches!(
+            self,
+            Item::WandLightning
+                | Item::WandConfuse
+                | Item::WandDigging
+                | Item::Sword
+                | Item::Greataxe
+                | Item::Bow
+                | Item::Crossbow
+                | Item::Shield
+                | Item::Helmet
+                | Item::BodyArmor
+                | Item::Cloak
+                | Item::Boots
+                | Item::Amulet
+                | Item::Ring
+        )
+    }
+
+    fn category(self) -> ItemCategory {
+        use Item::*;
+        match self {
+            Sword | Greataxe | Bow | Crossbow => ItemCategory::Weapon,
+            Arrow | Bolt => ItemCategory::Ammo,
+            Shield | Helmet | BodyArmor | Cloak | Boots => ItemCategory::Armor,
+            Amulet | Ring => ItemCategory::Jewelry,
+            Heal => ItemCategory::Potion,
+            Lightning | Confuse | Fireball | Identify | Recharge => ItemCategory::Scroll,
+            WandLightning | WandConfuse | WandDigging => ItemCategory::Wand,
+            Key => ItemCategory::Tool,
+        }
+    }
+
+    fn launcher(self) -> Option<(Item, i32, i32)> {
+        match self {
+            Item::Bow => Some((Item::Arrow, BOW_DAMAGE, BOW_RANGE)),
+            Item::Crossbow => Some((Item::Bolt, CROSSBOW_DAMAGE, CROSSBOW_RANGE)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum ItemCategory {
+    Weapon,
+    Ammo,
+    Armor,
+    Potion,
+    Scroll,
+    Wand,
+    Jewelry,
+    Tool,
+}
+
+impl std::fmt::Display for ItemCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ItemCategory::Weapon => write!(f, "Weapons"),
+            ItemCategory::Ammo => write!(f, "Ammunition"),
+            ItemCategory::Armor => write!(f, "Armor"),
+            ItemCategory::Potion => write!(f, "Potions"),
+            ItemCategory::Scroll => write!(f, "Scrolls"),
+            ItemCategory::Wand => write!(f, "Wands"),
+            ItemCategory::Jewelry => write!(f, "Jewelry"),
+            ItemCategory::Tool => write!(f, "Tools"),
+        }
+    }
 }
 
 struct Tcod {
@@ -193,6 +599,11 @@ struct Tcod {
     panel: Offscreen,
     fov: FovMap,
     mouse: Mouse,
+    camera: (i32, i32),
+    cursor: Option<(i32, i32)>,
+    keymap: Keymap,
+    activity: Option<Activity>,
+    count: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -201,6 +612,76 @@ struct Game {
     log: Messages,
     inventory: Vec<Object>,
     dungeon_level: u32,
+    #[serde(default)]
+    turn: u32,
+    #[serde(default)]
+    appearances: Vec<(Item, String)>,
+    #[serde(default)]
+    identified: Vec<Item>,
+    #[serde(default)]
+    remembered_items: Vec<(i32, i32, char, Color)>,
+}
+
+impl Game {
+    fn appearance(&self, item: Item) -> Option<&str> {
+        if self.identified.contains(&item) {
+            return None;
+        }
+        self.appearances
+            .iter()
+            .find(|&&(kind, _)| kind == item)
+            .map(|(_, appearance)| appearance.as_str())
+    }
+
+    fn identify(&mut self, item: Item) {
+        if !self.identified.contains(&item) {
+            self.identified.push(item);
+        }
+    }
+}
+
+const POTION_APPEARANCES: &[&str] = &[
+    "fizzy blue",
+    "murky green",
+    "bubbling red",
+    "cloudy white",
+    "smoky black",
+    "glowing orange",
+];
+const SCROLL_SYLLABLES: &[&str] = &[
+    "xy", "zzy", "foo", "bar", "kla", "tu", "nib", "ort", "ve", "lam", "hack", "ze", "mor", "pi",
+];
+
+fn random_appearances() -> Vec<(Item, String)> {
+    let mut rng = rand::thread_rng();
+    let mut potions = POTION_APPEARANCES.to_vec();
+    rng.shuffle(&mut potions);
+    let kinds = |category| {
+        Item::all()
+            .iter()
+            .copied()
+            .filter(move |item| item.category() == category)
+    };
+    let mut appearances: Vec<_> = kinds(ItemCategory::Potion)
+        .zip(potions)
+        .map(|(item, color)| (item, format!("{} potion", color)))
+        .collect();
+    for item in kinds(ItemCategory::Scroll) {
+        let label = loop {
+            let label: String = (0..rng.gen_range(2, 4))
+                .map(|_| SCROLL_SYLLABLES[rng.gen_range(0, SCROLL_SYLLABLES.len())])
+                .collect();
+            let label = label.to_uppercase();
+            if appearances
+                .iter()
+                .all(|(_, other)| !other.ends_with(&label))
+            {
+                break label;
+            }
+        };
+        appearances.push((item, format!("scroll labeled {}", label)));
+    }
+    appearances
 }
 
 struct Transition {
@@ -208,20 +689,85 @@ struct Transition {
     value: u32,
 }
 
+const OFF_HAND_POWER_PERCENT: i32 = 50;
+
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 struct Equipment {
     slot: Slot,
     equipped: bool,
+    #[serde(default)]
+    two_handed: bool,
+    #[serde(default)]
+    off_hand: bool,
     max_hp_bonus: i32,
     power_bonus: i32,
     defense_bonus: i32,
 }
 
+impl Equipment {
+    fn occupies(&self, slot: Slot) -> bool {
+        if !self.equipped {
+            return false;
+        }
+        match self.slot {
+            Slot::RightHand if self.two_handed => slot == Slot::RightHand || slot == Slot::LeftHand,
+            Slot::RightHand if self.off_hand => slot == Slot::LeftHand,
+            own_slot => own_slot == slot,
+        }
+    }
+
+    fn held_on(&self) -> String {
+        if self.two_handed {
+            "both hands".into()
+        } else if self.off_hand {
+            Slot::LeftHand.to_string()
+        } else {
+            self.slot.to_string()
+        }
+    }
+
+    fn effective_power_bonus(&self) -> i32 {
+        if self.off_hand {
+            self.power_bonus * OFF_HAND_POWER_PERCENT / 100
+        } else {
+            self.power_bonus
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Light {
+    radius: i32,
+    color: Color,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 enum Slot {
     LeftHand,
     RightHand,
     Head,
+    Body,
+    Cloak,
+    Feet,
+    Neck,
+    Ring,
+    Quiver,
+}
+
+impl Slot {
+    fn all() -> &'static [Slot] {
+        use Slot::*;
+        &[
+            Head, Neck, Cloak, Body, RightHand, LeftHand, Ring, Feet, Quiver,
+        ]
+    }
+
+    fn capacity(self) -> usize {
+        match self {
+            Slot::Ring => 2,
+            _ => 1,
+        }
+    }
 }
 
 impl std::fmt::Display for Slot {
@@ -230,6 +776,51 @@ impl std::fmt::Display for Slot {
             Slot::LeftHand => write!(f, "left hand"),
             Slot::RightHand => write!(f, "right hand"),
             Slot::Head => write!(f, "head"),
+            Slot::Body => write!(f, "body"),
+            Slot::Cloak => write!(f, "shoulders"),
+            Slot::Feet => write!(f, "feet"),
+            Slot::Neck => write!(f, "neck"),
+            Slot::Ring => write!(f, "finger"),
+            Slot::Quiver => write!(f, "quiver"),
+        }
+    }
+}
+
+const CARRY_BASE: i32 = 40;
+const CARRY_PER_POWER: i32 = 10;
+const MAX_LOAD_FACTOR: i32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Burden {
+    Unburdened,
+    Burdened,
+    Overloaded,
+}
+
+impl Burden {
+    fn penalty(self) -> i32 {
+        match self {
+            Burden::Unburdened => 0,
+            Burden::Burdened => 1,
+            Burden::Overloaded => 3,
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Burden::Unburdened => WHITE,
+            Burden::Burdened => YELLOW,
+            Burden::Overloaded => ORANGE,
+        }
+    }
+}
+
+impl std::fmt::Display for Burden {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Burden::Unburdened => write!(f, "unburdened"),
+            Burden::Burdened => write!(f, "burdened"),
+            Burden::Overloaded => write!(f, "overloaded"),
         }
     }
 }
@@ -248,56 +839,192 @@ fn target_tile(
     game: &mut Game,
     max_range: Option<f32>,
 ) -> Option<(i32, i32)> {
-    loop {
+    let mut targets: Vec<_> = objects
+        .iter()
+        .enumerate()
+        .filter(|&(id, object)| {
+            id != PLAYER
+                && object.fighter.is_some()
+                && is_visible(object.x, object.y, &game.map, &tcod.fov)
+        })
+        .map(|(_, object)| object)
+        .collect();
+    targets.sort_by(|a, b| {
+        let a = objects[PLAYER].distance_to(a);
+        let b = objects[PLAYER].distance_to(b);
+        a.partial_cmp(&b).unwrap()
+    });
+    let mut next_target = 0;
+
+    tcod.cursor = Some(objects[PLAYER].pos());
+    let target = loop {
+        let (mut x, mut y) = tcod.cursor.unwrap();
+        render_all(tcod, objects, game, false);
         tcod.root.flush();
         let event = input::check_for_event(input::KEY_PRESS | input::MOUSE).map(|e| e.1);
-        let mut key = None;
+        let mut confirm = false;
         match event {
-            Some(Event::Mouse(m)) => tcod.mouse = m,
-            Some(Event::Key(k)) => key = Some(k),
+            Some(Event::Mouse(mouse)) => {
+                tcod.mouse = mouse;
+                if mouse.rbutton_pressed {
+                    break None;
+                }
+                if let Some((mouse_x, mouse_y)) = mouse_map_pos(mouse, tcod.camera) {
+                    x = mouse_x;
+                    y = mouse_y;
+                    confirm = mouse.lbutton_pressed;
+                }
+            }
+            Some(Event::Key(Key { code: Escape, .. })) => break None,
+            Some(Event::Key(Key { code: Enter, .. }))
+            | Some(Event::Key(Key {
+                code: NumPadEnter, ..
+            })) => confirm = true,
+            Some(Event::Key(Key { code: Tab, .. })) if !targets.is_empty() => {
+                let target = targets[next_target % targets.len()];
+                x = target.x;
+                y = target.y;
+                next_target += 1;
+            }
+            Some(Event::Key(key)) => {
+                if let Some((dx, dy)) = tcod.keymap.direction(key) {
+                    let (camera_x, camera_y) = tcod.camera;
+                    x = (x + dx).clamp(camera_x, camera_x + CAMERA_WIDTH - 1);
+                    y = (y + dy).clamp(camera_y, camera_y + CAMERA_HEIGHT - 1);
+                }
+            }
             None => {}
         }
-        render_all(tcod, objects, game, false);
-        let (x, y) = (tcod.mouse.cx as i32, tcod.mouse.cy as i32);
+        tcod.cursor = Some((x, y));
 
-        let in_fov = (x < MAP_WIDTH) && (y < MAP_HEIGHT) && tcod.fov.is_in_fov(x, y);
-        let in_range = max_range.map_or(true, |range| objects[PLAYER].distance(x, y) <= range);
-        if tcod.mouse.lbutton_pressed && in_fov && in_range {
-            return Some((x, y));
+        let in_fov = is_visible(x, y, &game.map, &tcod.fov);
+        let in_range = max_range.is_none_or(|range| objects[PLAYER].distance(x, y) <= range);
+        if confirm && in_fov && in_range {
+            break Some((x, y));
         }
+    };
+    tcod.cursor = None;
+    tcod.mouse.lbutton_pressed = false;
+    tcod.mouse.rbutton_pressed = false;
+    target
+}
 
-        let escape = key.map_or(false, |k| k.code == Escape);
-        if tcod.mouse.rbutton_pressed || escape {
-            return None;
+fn look(tcod: &mut Tcod, objects: &[Object], game: &mut Game) {
+    game.log.add(
+        "Move the cursor or press Tab to look around, Escape to stop.",
+        LIGHT_CYAN,
+    );
+    if let Some((x, y)) = target_tile(tcod, objects, game, None) {
+        let names = get_names_at(x, y, objects, game, &tcod.fov);
+        if names.is_empty() {
+            game.log.add("You see nothing of interest.", WHITE);
+        } else {
+            game.log.add(format!("You see: {}.", names), WHITE);
+        }
+        let visible = is_visible(x, y, &game.map, &tcod.fov);
+        let monsters = objects
+            .iter()
+            .filter(|obj| visible && obj.pos() == (x, y) && obj.alive);
+        for monster in monsters {
+            let equipped: Vec<_> = monster
+                .inventory
+                .iter()
+                .filter(|item| item.equipment.is_some_and(|e| e.equipped))
+                .map(|item| item.name.clone())
+                .collect();
+            if !equipped.is_empty() {
+                game.log.add(
+                    format!(
+                        "The {} is equipped with {}.",
+                        monster.name,
+                        equipped.join(", ")
+                    ),
+                    WHITE,
+                );
+            }
         }
     }
 }
 
+fn plural(name: &str) -> String {
+    match name.find(" of ").or_else(|| name.find(" labeled ")) {
+        Some(index) => format!("{}s{}", &name[..index], &name[index..]),
+        None => format!("{}s", name),
+    }
+}
+
+const INVENTORY_LETTERS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+fn inventory_letter(item: &Object, inventory: &[Object]) -> Option<char> {
+    let free = |letter: char| inventory.iter().all(|other| other.letter != Some(letter));
+    match item.letter {
+        Some(letter) if free(letter) => Some(letter),
+        _ => INVENTORY_LETTERS.chars().find(|&letter| free(letter)),
+    }
+}
+
 fn pick_item_up(object_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
-    if game.inventory.len() >= 26 {
+    let letter = inventory_letter(&objects[object_id], &game.inventory);
+    let stack = game
+        .inventory
+        .iter()
+        .position(|item| item.stacks_with(&objects[object_id]));
+    let burden = objects[PLAYER].burden(game);
+    let weight = inventory_weight(&game.inventory) + objects[object_id].total_weight();
+    if weight > objects[PLAYER].carry_capacity() * MAX_LOAD_FACTOR {
+        game.log.add(
+            format!(
+                "The {} is too heavy for you to carry.",
+                objects[object_id].known_name(game)
+            ),
+            RED,
+        );
+    } else if let Some(stack) = stack {
+        let item = objects.swap_remove(object_id);
+        game.inventory[stack].count += item.count;
+        game.log.add(
+            format!(
+                "You picked up {}! You now have {}.",
+                item.counted_name(game),
+                game.inventory[stack].display_name(game)
+            ),
+            GREEN,
+        );
+    } else if letter.is_none() {
         game.log.add(
             format!(
                 "Your inventory is full, cannot pick up {}.",
-                objects[object_id].name
+                objects[object_id].known_name(game)
             ),
             RED,
         );
     } else {
-        let item = objects.swap_remove(object_id);
+        let mut item = objects.swap_remove(object_id);
+        item.letter = letter;
         game.log
-            .add(format!("You picked up a {}!", item.name), GREEN);
+            .add(format!("You picked up {}!", item.counted_name(game)), GREEN);
         let index = game.inventory.len();
-        let slot = item.equipment.map(|e| e.slot);
+        let equipment = item.equipment;
         game.inventory.push(item);
 
-        if let Some(slot) = slot {
-            if get_equipped_in_slot(slot, &game.inventory).is_none() {
+        if let Some(equipment) = equipment {
+            if equip_target(equipment, &game.inventory) == (false, vec![]) {
                 game.inventory[index].equip(&mut game.log);
             }
         }
     }
+    let new_burden = objects[PLAYER].burden(game);
+    if new_burden != burden && new_burden != Burden::Unburdened {
+        game.log
+            .add(format!("You are {}.", new_burden), new_burden.color());
+    }
+}
+
+fn inventory_weight(inventory: &[Object]) -> i32 {
+    inventory.iter().map(Object::total_weight).sum()
 }
 
+#[derive(PartialEq)]
 enum UseResult {
     UsedUp,
     Cancelled,
@@ -327,7 +1054,12 @@ fn cast_heal(
 const LIGHTNING_DAMAGE: i32 = 40;
 const LIGHTNING_RANGE: i32 = 5;
 
-fn closest_monster(max_range: i32, objects: &mut [Object], tcod: &Tcod) -> Option<usize> {
+fn closest_monster(
+    max_range: i32,
+    objects: &mut [Object],
+    map: &Map,
+    tcod: &Tcod,
+) -> Option<usize> {
     let mut closest_enemy = None;
     let mut closest_dist = (max_range + 1) as f32;
 
@@ -335,7 +1067,7 @@ fn closest_monster(max_range: i32, objects: &mut [Object], tcod: &Tcod) -> Optio
         if (id != PLAYER)
             && object.fighter.is_some()
             && object.ai.is_some()
-            && tcod.fov.is_in_fov(object.x, object.y)
+            && is_visible(object.x, object.y, map, &tcod.fov)
         {
             let dist = objects[PLAYER].distance_to(object);
             if dist < closest_dist {
@@ -353,7 +1085,7 @@ fn cast_lightning(
     game: &mut Game,
     tcod: &mut Tcod,
 ) -> UseResult {
-    let monster_id = closest_monster(LIGHTNING_RANGE, objects, tcod);
+    let monster_id = closest_monster(LIGHTNING_RANGE, objects, &game.map, tcod);
     if let Some(monster_id) = monster_id {
         game.log.add(
             format!(
@@ -383,7 +1115,7 @@ fn cast_confuse(
     tcod: &mut Tcod,
 ) -> UseResult {
     game.log.add(
-        "Left-click an enemy to confuse it, or right-click to cancel.",
+        "Left-click or select an enemy to confuse it, or right-click to cancel.",
         LIGHT_CYAN,
     );
     let monster_id = target_monster(tcod, objects, game, Some(CONFUSE_RANGE as f32));
@@ -414,7 +1146,7 @@ fn cast_fireball(
     tcod: &mut Tcod,
 ) -> UseResult {
     game.log.add(
-        "Left-click a target tile for the fireball, or right-click to cancel.",
+        "Left-click or select a target tile for the fireball, or right-click to cancel.",
         LIGHT_CYAN,
     );
     let (x, y) = match target_tile(tcod, objects, game, None) {
@@ -452,58 +1184,478 @@ fn cast_fireball(
 const FIREBALL_RADIUS: i32 = 3;
 const FIREBALL_DAMAGE: i32 = 25;
 
-fn drop_item(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
-    let mut item = game.inventory.remove(inventory_id);
-    if item.equipment.is_some() {
-        item.dequip(&mut game.log);
+fn cast_identify(
+    _inventory_id: usize,
+    _objects: &mut [Object],
+    game: &mut Game,
+    tcod: &mut Tcod,
+) -> UseResult {
+    let target = inventory_menu(
+        game,
+        "Press the key next to an item to identify it, or any other to cancel.\n",
+        &mut tcod.root,
+    );
+    let target = match target {
+        Some(target) => target,
+        None => return UseResult::Cancelled,
+    };
+    let item = game.inventory[target].item;
+    match item.filter(|&item| game.appearance(item).is_some()) {
+        Some(item) => {
+            let appearance = game.inventory[target].known_name(game);
+            game.identify(item);
+            game.log.add(
+                format!("The {} is a {}.", appearance, game.inventory[target].name),
+                LIGHT_CYAN,
+            );
+            UseResult::UsedUp
+        }
+        None => {
+            game.log.add("You already know what that is.", WHITE);
+            UseResult::Cancelled
+        }
     }
-    item.set_pos(objects[PLAYER].x, objects[PLAYER].y);
-    game.log
-        .add(format!("You dropped a {}.", item.name), YELLOW);
-    objects.push(item);
 }
 
-fn toggle_equipment(
-    inventory_id: usize,
+const WAND_MIN_CHARGES: u32 = 3;
+const WAND_MAX_CHARGES: u32 = 8;
+const RECHARGE_AMOUNT: u32 = 4;
+const DIG_RANGE: i32 = 8;
+
+fn cast_recharge(
+    _inventory_id: usize,
     _objects: &mut [Object],
     game: &mut Game,
-    _tcod: &mut Tcod,
+    tcod: &mut Tcod,
 ) -> UseResult {
-    let equipment = match game.inventory[inventory_id].equipment {
-        Some(equipment) => equipment,
+    let target = inventory_menu(
+        game,
+        "Press the key next to an item to recharge it, or any other to cancel.\n",
+        &mut tcod.root,
+    );
+    let target = match target {
+        Some(target) => target,
         None => return UseResult::Cancelled,
     };
-    if equipment.equipped {
-        game.inventory[inventory_id].dequip(&mut game.log);
-    } else {
-        if let Some(old_equipment) = get_equipped_in_slot(equipment.slot, &game.inventory) {
-            game.inventory[old_equipment].dequip(&mut game.log);
+    match game.inventory[target].charges {
+        Some(charges) => {
+            let charges = cmp::min(charges + RECHARGE_AMOUNT, WAND_MAX_CHARGES);
+            game.inventory[target].charges = Some(charges);
+            game.log.add(
+                format!(
+                    "The {} glows brightly and now holds {} charges.",
+                    game.inventory[target].name, charges
+                ),
+                LIGHT_CYAN,
+            );
+            UseResult::UsedUp
+        }
+        None => {
+            game.log.add("That cannot be recharged.", WHITE);
+            UseResult::Cancelled
         }
-        game.inventory[inventory_id].equip(&mut game.log);
     }
-    UseResult::UsedAndKept
 }
 
-fn use_item(
-    inventory_id: usize,
+fn cast_dig(
+    _inventory_id: usize,
     objects: &mut [Object],
     game: &mut Game,
     tcod: &mut Tcod,
 ) -> UseResult {
-    use Item::*;
-    if let Some(item) = game.inventory[inventory_id].item {
-        let on_use = match item {
-            Heal => cast_heal,
-            Lightning => cast_lightning,
-            Confuse => cast_confuse,
-            Fireball => cast_fireball,
-            Sword => toggle_equipment,
-            Shield => toggle_equipment,
-        };
+    game.log.add(
+        "Left-click or select a tile to dig towards, or right-click to cancel.",
+        LIGHT_CYAN,
+    );
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let (x, y) = match target_tile(tcod, objects, game, None) {
+        Some(target) if target != (player_x, player_y) => target,
+        _ => return UseResult::Cancelled,
+    };
+    let scale = DIG_RANGE as f32 / objects[PLAYER].distance(x, y);
+    let end = (
+        player_x + ((x - player_x) as f32 * scale).round() as i32,
+        player_y + ((y - player_y) as f32 * scale).round() as i32,
+    );
+    let mut dug = 0;
+    for (x, y) in Line::new((player_x, player_y), end) {
+        if x <= 0 || x >= MAP_WIDTH - 1 || y <= 0 || y >= MAP_HEIGHT - 1 {
+            break;
+        }
+        if game.map[x as usize][y as usize].terrain == Terrain::Wall {
+            let Tile { explored, lit, .. } = game.map[x as usize][y as usize];
+            game.map[x as usize][y as usize] = Tile::empty();
+            game.map[x as usize][y as usize].explored = explored;
+            game.map[x as usize][y as usize].lit = lit;
+            update_fov_tile(x, y, &game.map, &mut tcod.fov);
+            for object in objects.iter_mut() {
+                if object.pos() == (x, y) && object.light.is_some() && object.fighter.is_none() {
+                    object.light = None;
+                    object.color = DARKER_SEPIA;
+                    object.name = "burnt-out torch".into();
+                    game.log
+                        .add("A torch falls from the wall and gutters out.", WHITE);
+                }
+            }
+            dug += 1;
+        }
+    }
+    if dug == 0 {
+        game.log.add("The wand's beam finds nothing to dig.", WHITE);
+        return UseResult::Cancelled;
+    }
+    game.log
+        .add("The wand bores a tunnel through the rock!", LIGHT_GREEN);
+    UseResult::UsedUp
+}
+
+fn drop_item(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
+    let mut item = game.inventory.remove(inventory_id);
+    if item.equipment.is_some() {
+        item.dequip(&mut game.log);
+    }
+    let (x, y) = objects[PLAYER].pos();
+    let terrain = game.map[x as usize][y as usize].terrain;
+    if terrain.swallows_items() {
+        game.log.add(
+            format!(
+                "You dropped {}. It is lost in the {}.",
+                item.counted_name(game),
+                terrain
+            ),
+            YELLOW,
+        );
+        return;
+    }
+    item.set_pos(x, y);
+    game.log
+        .add(format!("You dropped {}.", item.counted_name(game)), YELLOW);
+    objects.push(item);
+}
+
+const THROW_RANGE: i32 = 8;
+const THROW_DAMAGE_FACTOR: i32 = 2;
+
+fn take_one_from_inventory(inventory_id: usize, game: &mut Game) -> Object {
+    let stack = &mut game.inventory[inventory_id];
+    if stack.count > 1 {
+        stack.count -= 1;
+        let mut item = stack.clone();
+        item.count = 1;
+        if let Some(ref mut equipment) = item.equipment {
+            equipment.equipped = false;
+            equipment.off_hand = false;
+        }
+        item
+    } else {
+        let mut item = game.inventory.remove(inventory_id);
+        if item.equipment.is_some() {
+            item.dequip(&mut game.log);
+        }
+        item
+    }
+}
+
+fn trace_projectile(
+    start: (i32, i32),
+    target: (i32, i32),
+    objects: &[Object],
+    map: &Map,
+) -> ((i32, i32), Option<usize>) {
+    let mut landing = start;
+    for (x, y) in Line::new(start, target) {
+        if map[x as usize][y as usize].blocked {
+            break;
+        }
+        landing = (x, y);
+        let monster = objects
+            .iter()
+            .position(|object| object.pos() == (x, y) && object.fighter.is_some() && object.alive);
+        if monster.is_some() {
+            return (landing, monster);
+        }
+    }
+    (landing, None)
+}
+
+fn land_item(mut item: Object, x: i32, y: i32, objects: &mut Vec<Object>, game: &mut Game) {
+    let terrain = game.map[x as usize][y as usize].terrain;
+    if terrain.swallows_items() {
+        game.log.add(
+            format!("The {} is lost in the {}.", item.known_name(game), terrain),
+            YELLOW,
+        );
+    } else {
+        item.set_pos(x, y);
+        objects.push(item);
+    }
+}
+
+fn throw_item(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) -> PlayerAction {
+    let inventory_id = match inventory_menu(
+        game,
+        "Press the key next to an item to throw it, or any other to cancel.\n",
+        &mut tcod.root,
+    ) {
+        Some(inventory_id) => inventory_id,
+        None => return PlayerAction::DidnTakeTurn,
+    };
+    game.log.add(
+        "Left-click or select a target tile to throw at, or right-click to cancel.",
+        LIGHT_CYAN,
+    );
+    let target = match target_tile(tcod, objects, game, Some(THROW_RANGE as f32)) {
+        Some(target) if target != objects[PLAYER].pos() => target,
+        _ => return PlayerAction::DidnTakeTurn,
+    };
+
+    let item = take_one_from_inventory(inventory_id, game);
+    let name = item.known_name(game);
+    game.log.add(format!("You throw the {}.", name), WHITE);
+    let ((x, y), hit) = trace_projectile(objects[PLAYER].pos(), target, objects, &game.map);
+
+    if item.item.map(Item::category) == Some(ItemCategory::Potion) {
+        game.log.add(format!("The {} shatters!", name), LIGHT_BLUE);
+        if let (Some(monster_id), Some(Item::Heal)) = (hit, item.item) {
+            game.log.add(
+                format!("The {} looks healthier.", objects[monster_id].name),
+                LIGHT_VIOLET,
+            );
+            objects[monster_id].heal(HEAL_AMOUNT, game);
+            game.identify(Item::Heal);
+        }
+        return PlayerAction::TookTurn;
+    }
+
+    if let Some(monster_id) = hit {
+        let power = item
+            .equipment
+            .filter(|_| item.item.map(Item::category) == Some(ItemCategory::Weapon))
+            .map_or(0, |e| e.power_bonus * THROW_DAMAGE_FACTOR);
+        let damage = power - objects[monster_id].defense(game);
+        if damage > 0 {
+            game.log.add(
+                format!(
+                    "The {} hits the {} for {} hit points.",
+                    name, objects[monster_id].name, damage
+                ),
+                WHITE,
+            );
+            if let Some(xp) = objects[monster_id].take_damage(damage, game) {
+                objects[PLAYER].fighter.as_mut().unwrap().xp += xp;
+            }
+        } else {
+            game.log.add(
+                format!("The {} bounces off the {}.", name, objects[monster_id].name),
+                WHITE,
+            );
+        }
+    }
+    land_item(item, x, y, objects, game);
+    PlayerAction::TookTurn
+}
+
+const BOW_DAMAGE: i32 = 6;
+const BOW_RANGE: i32 = 10;
+const CROSSBOW_DAMAGE: i32 = 9;
+const CROSSBOW_RANGE: i32 = 8;
+const FIRE_ACCURACY: i32 = 95;
+const FIRE_ACCURACY_FALLOFF: i32 = 6;
+const AMMO_BREAK_CHANCE: i32 = 20;
+const AMMO_MIN_STACK: u32 = 4;
+const AMMO_MAX_STACK: u32 = 9;
+
+fn fire_launcher(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) -> PlayerAction {
+    let launcher = get_equipped_in_slot(Slot::RightHand, &game.inventory)
+        .into_iter()
+        .find(|&inventory_id| {
+            game.inventory[inventory_id]
+                .item
+                .is_some_and(|item| item.launcher().is_some())
+        });
+    let (launcher, ammo, damage, range) = match launcher {
+        Some(launcher) => {
+            let (ammo, damage, range) = game.inventory[launcher].item.unwrap().launcher().unwrap();
+            (game.inventory[launcher].name.clone(), ammo, damage, range)
+        }
+        None => {
+            game.log.add("You have no ranged weapon ready.", WHITE);
+            return PlayerAction::DidnTakeTurn;
+        }
+    };
+    let quiver = get_equipped_in_slot(Slot::Quiver, &game.inventory)
+        .into_iter()
+        .find(|&inventory_id| game.inventory[inventory_id].item == Some(ammo));
+    let quiver = match quiver {
+        Some(quiver) => quiver,
+        None => {
+            game.log.add(
+                format!("You have no ammunition for your {}.", launcher),
+                WHITE,
+            );
+            return PlayerAction::DidnTakeTurn;
+        }
+    };
+    game.log.add(
+        "Left-click or select a target tile to fire at, or right-click to cancel.",
+        LIGHT_CYAN,
+    );
+    let target = match target_tile(tcod, objects, game, Some(range as f32)) {
+        Some(target) if target != objects[PLAYER].pos() => target,
+        _ => return PlayerAction::DidnTakeTurn,
+    };
+
+    let projectile = take_one_from_inventory(quiver, game);
+    let name = projectile.name.clone();
+    game.log.add(format!("You fire your {}.", launcher), WHITE);
+    let ((x, y), hit) = trace_projectile(objects[PLAYER].pos(), target, objects, &game.map);
+    if let Some(monster_id) = hit {
+        let distance = objects[PLAYER].distance(x, y);
+        let accuracy = FIRE_ACCURACY - (distance * FIRE_ACCURACY_FALLOFF as f32) as i32;
+        if rand::thread_rng().gen_range(0, 100) < accuracy {
+            let damage = damage - objects[monster_id].defense(game);
+            if damage > 0 {
+                game.log.add(
+                    format!(
+                        "Your {} hits the {} for {} hit points.",
+                        name, objects[monster_id].name, damage
+                    ),
+                    WHITE,
+                );
+                if let Some(xp) = objects[monster_id].take_damage(damage, game) {
+                    objects[PLAYER].fighter.as_mut().unwrap().xp += xp;
+                }
+            } else {
+                game.log.add(
+                    format!(
+                        "Your {} glances off the {}.",
+                        name, objects[monster_id].name
+                    ),
+                    WHITE,
+                );
+            }
+        } else {
+            game.log.add(
+                format!("Your {} misses the {}.", name, objects[monster_id].name),
+                WHITE,
+            );
+        }
+    }
+    if rand::thread_rng().gen_range(0, 100) < AMMO_BREAK_CHANCE {
+        game.log.add(format!("The {} breaks.", name), LIGHT_GREY);
+    } else {
+        land_item(projectile, x, y, objects, game);
+    }
+    PlayerAction::TookTurn
+}
+
+fn toggle_equipment(
+    inventory_id: usize,
+    _objects: &mut [Object],
+    game: &mut Game,
+    _tcod: &mut Tcod,
+) -> UseResult {
+    let equipment = match game.inventory[inventory_id].equipment {
+        Some(equipment) => equipment,
+        None => return UseResult::Cancelled,
+    };
+    if equipment.equipped {
+        game.inventory[inventory_id].dequip(&mut game.log);
+    } else {
+        let (off_hand, displaced) = equip_target(equipment, &game.inventory);
+        for old_equipment in displaced {
+            game.inventory[old_equipment].dequip(&mut game.log);
+        }
+        if let Some(ref mut equipment) = game.inventory[inventory_id].equipment {
+            equipment.off_hand = off_hand;
+        }
+        game.inventory[inventory_id].equip(&mut game.log);
+    }
+    UseResult::UsedAndKept
+}
+
+fn use_key(
+    _inventory_id: usize,
+    objects: &mut [Object],
+    game: &mut Game,
+    tcod: &mut Tcod,
+) -> UseResult {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let locked_door = adjacent_doors(player_x, player_y, &game.map)
+        .into_iter()
+        .find(|&(x, y)| game.map[x as usize][y as usize].door == Some(Door::Locked));
+    if let Some((x, y)) = locked_door {
+        set_door(x, y, Door::Open, &mut game.map, &mut tcod.fov);
+        game.log.add("You unlock the door with a key.", LIGHT_GREEN);
+        UseResult::UsedUp
+    } else {
+        game.log.add("There is no locked door next to you.", RED);
+        UseResult::Cancelled
+    }
+}
+
+fn use_item(
+    inventory_id: usize,
+    objects: &mut [Object],
+    game: &mut Game,
+    tcod: &mut Tcod,
+) -> UseResult {
+    use Item::*;
+    if let Some(item) = game.inventory[inventory_id].item {
+        let on_use = match item {
+            Heal => cast_heal,
+            Lightning => cast_lightning,
+            Confuse => cast_confuse,
+            Fireball => cast_fireball,
+            Identify => cast_identify,
+            Recharge => cast_recharge,
+            WandLightning => cast_lightning,
+            WandConfuse => cast_confuse,
+            WandDigging => cast_dig,
+            Sword => toggle_equipment,
+            Greataxe => toggle_equipment,
+            Bow => toggle_equipment,
+            Crossbow => toggle_equipment,
+            Arrow => toggle_equipment,
+            Bolt => toggle_equipment,
+            Shield => toggle_equipment,
+            Helmet => toggle_equipment,
+            BodyArmor => toggle_equipment,
+            Cloak => toggle_equipment,
+            Boots => toggle_equipment,
+            Amulet => toggle_equipment,
+            Ring => toggle_equipment,
+            Key => use_key,
+        };
+        let name = game.inventory[inventory_id].name.clone();
+        if game.inventory[inventory_id].charges == Some(0) {
+            game.log
+                .add(format!("The {} is out of charges.", name), WHITE);
+            return UseResult::Cancelled;
+        }
+        let appearance = game.appearance(item).map(String::from);
         let on_use_result = on_use(inventory_id, objects, game, tcod);
+        let on_use_result = match (on_use_result, game.inventory[inventory_id].charges) {
+            (UseResult::UsedUp, Some(charges)) => {
+                game.inventory[inventory_id].charges = Some(charges - 1);
+                UseResult::UsedAndKept
+            }
+            (on_use_result, _) => on_use_result,
+        };
+        if on_use_result != UseResult::Cancelled {
+            if let Some(appearance) = appearance {
+                game.identify(item);
+                game.log
+                    .add(format!("The {} was a {}.", appearance, name), LIGHT_CYAN);
+            }
+        }
         match on_use_result {
             UseResult::UsedUp => {
-                game.inventory.remove(inventory_id);
+                if game.inventory[inventory_id].count > 1 {
+                    game.inventory[inventory_id].count -= 1;
+                } else {
+                    game.inventory.remove(inventory_id);
+                }
             }
             UseResult::UsedAndKept => {}
             UseResult::Cancelled => {
@@ -520,7 +1672,7 @@ fn use_item(
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct Object {
     x: i32,
     y: i32,
@@ -535,6 +1687,24 @@ struct Object {
     always_visible: bool,
     level: i32,
     equipment: Option<Equipment>,
+    #[serde(default)]
+    light: Option<Light>,
+    #[serde(default = "default_count")]
+    count: u32,
+    #[serde(default)]
+    letter: Option<char>,
+    #[serde(default)]
+    weight: i32,
+    #[serde(default)]
+    inventory: Vec<Object>,
+    #[serde(default)]
+    charges: Option<u32>,
+    #[serde(default)]
+    monster: Option<Monster>,
+    #[serde(default)]
+    delay: i32,
+    #[serde(default)]
+    noticed: bool,
 }
 
 impl Object {
@@ -553,10 +1723,79 @@ impl Object {
             always_visible: false,
             level: 1,
             equipment: None,
+            light: None,
+            count: 1,
+            letter: None,
+            weight: 0,
+            inventory: vec![],
+            charges: None,
+            monster: None,
+            delay: 0,
+            noticed: false,
+        }
+    }
+
+    pub fn known_name(&self, game: &Game) -> String {
+        match self.item.and_then(|item| game.appearance(item)) {
+            Some(appearance) => appearance.into(),
+            None => self.name.clone(),
+        }
+    }
+
+    pub fn display_name(&self, game: &Game) -> String {
+        let name = if self.count > 1 {
+            format!("{} {}", self.count, plural(&self.known_name(game)))
+        } else {
+            self.known_name(game)
+        };
+        match self.charges {
+            Some(1) => format!("{} (1 charge)", name),
+            Some(charges) => format!("{} ({} charges)", name, charges),
+            None => name,
+        }
+    }
+
+    pub fn counted_name(&self, game: &Game) -> String {
+        if self.count > 1 {
+            self.display_name(game)
+        } else {
+            format!("a {}", self.known_name(game))
+        }
+    }
+
+    pub fn total_weight(&self) -> i32 {
+        self.weight * self.count as i32
+    }
+
+    pub fn carry_capacity(&self) -> i32 {
+        CARRY_BASE + self.fighter.map_or(0, |f| f.base_power) * CARRY_PER_POWER
+    }
+
+    pub fn is_player(&self) -> bool {
+        self.fighter
+            .is_some_and(|f| f.on_death == DeathCallback::Player)
+    }
+
+    pub fn burden(&self, game: &Game) -> Burden {
+        if !self.is_player() {
+            return Burden::Unburdened;
         }
+        let weight = inventory_weight(&game.inventory);
+        let capacity = self.carry_capacity();
+        if weight * 2 > capacity * 3 {
+            Burden::Overloaded
+        } else if weight > capacity {
+            Burden::Burdened
+        } else {
+            Burden::Unburdened
+        }
+    }
+
+    pub fn stacks_with(&self, other: &Object) -> bool {
+        self.item.is_some_and(Item::stackable) && self.item == other.item && self.name == other.name
     }
 
-    pub fn draw(&self, con: &mut Console) {
+    pub fn draw(&self, con: &mut dyn Console) {
         con.set_default_foreground(self.color);
         con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
     }
@@ -642,7 +1881,7 @@ impl Object {
             if !equipment.equipped {
                 equipment.equipped = true;
                 log.add(
-                    format!("Equipped {} on {}.", self.name, equipment.slot),
+                    format!("Equipped {} on {}.", self.name, equipment.held_on()),
                     LIGHT_GREEN,
                 );
             }
@@ -664,11 +1903,12 @@ impl Object {
         }
         if let Some(ref mut equipment) = self.equipment {
             if equipment.equipped {
-                equipment.equipped = false;
                 log.add(
-                    format!("Dequipped {} from {}.", self.name, equipment.slot),
+                    format!("Dequipped {} from {}.", self.name, equipment.held_on()),
                     LIGHT_YELLOW,
                 );
+                equipment.equipped = false;
+                equipment.off_hand = false;
             }
         } else {
             log.add(
@@ -683,9 +1923,9 @@ impl Object {
         let bonus: i32 = self
             .get_all_equipped(game)
             .iter()
-            .map(|e| e.power_bonus)
+            .map(|e| e.effective_power_bonus())
             .sum();
-        base_power + bonus
+        base_power + bonus - self.burden(game).penalty()
     }
 
     pub fn defense(&self, game: &Game) -> i32 {
@@ -695,7 +1935,7 @@ impl Object {
             .iter()
             .map(|e| e.defense_bonus)
             .sum();
-        base_defense + bonus
+        base_defense + bonus - self.burden(game).penalty()
     }
 
     pub fn max_hp(&self, game: &Game) -> i32 {
@@ -709,15 +1949,24 @@ impl Object {
     }
 
     pub fn get_all_equipped(&self, game: &Game) -> Vec<Equipment> {
-        if self.name == "player" {
-            game.inventory
-                .iter()
-                .filter(|item| item.equipment.map_or(false, |e| e.equipped))
-                .map(|item| item.equipment.unwrap())
-                .collect()
+        let inventory = if self.is_player() {
+            &game.inventory
         } else {
-            vec![]
+            &self.inventory
+        };
+        inventory
+            .iter()
+            .filter(|item| item.equipment.is_some_and(|e| e.equipped))
+            .map(|item| item.equipment.unwrap())
+            .collect()
+    }
+
+    pub fn give_equipment(&mut self, item: Item) {
+        let mut object = create_item(item, self.x, self.y);
+        if let Some(ref mut equipment) = object.equipment {
+            equipment.equipped = true;
         }
+        self.inventory.push(object);
     }
 }
 
@@ -752,27 +2001,39 @@ fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut
     }
 }
 
-fn ai_take_turn(monster_id: usize, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) {
+fn ai_take_turn(monster_id: usize, objects: &mut [Object], game: &mut Game, fov_map: &mut FovMap) {
     use Ai::*;
 
+    if objects[monster_id].delay > 0 {
+        objects[monster_id].delay -= 1;
+        return;
+    }
     if let Some(ai) = objects[monster_id].ai.take() {
         let new_ai = match ai {
             Basic => ai_basic(monster_id, objects, game, fov_map),
             Confused {
                 previous_ai,
                 num_turns,
-            } => ai_confused(monster_id, objects, game, previous_ai, num_turns),
+            } => ai_confused(monster_id, objects, game, fov_map, previous_ai, num_turns),
+            Alerted { x, y, num_turns } => {
+                ai_alerted(monster_id, objects, game, fov_map, x, y, num_turns)
+            }
         };
         objects[monster_id].ai = Some(new_ai);
     }
 }
 
-fn ai_basic(monster_id: usize, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) -> Ai {
+fn ai_basic(
+    monster_id: usize,
+    objects: &mut [Object],
+    game: &mut Game,
+    fov_map: &mut FovMap,
+) -> Ai {
     let (monster_x, monster_y) = objects[monster_id].pos();
     if fov_map.is_in_fov(monster_x, monster_y) {
         if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
             let (player_x, player_y) = objects[PLAYER].pos();
-            move_towards(monster_id, player_x, player_y, &game.map, objects);
+            monster_move_towards(monster_id, player_x, player_y, objects, game, fov_map);
         } else if objects[PLAYER].fighter.map_or(false, |f| f.hp > 0) {
             let (monster, player) = mut_two(monster_id, PLAYER, objects);
             monster.attack(player, game);
@@ -781,10 +2042,68 @@ fn ai_basic(monster_id: usize, objects: &mut [Object], game: &mut Game, fov_map:
     Ai::Basic
 }
 
+fn ai_alerted(
+    monster_id: usize,
+    objects: &mut [Object],
+    game: &mut Game,
+    fov_map: &mut FovMap,
+    x: i32,
+    y: i32,
+    num_turns: i32,
+) -> Ai {
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    if fov_map.is_in_fov(monster_x, monster_y) {
+        return ai_basic(monster_id, objects, game, fov_map);
+    }
+    if num_turns <= 0 || (monster_x, monster_y) == (x, y) {
+        return Ai::Basic;
+    }
+    monster_move_towards(monster_id, x, y, objects, game, fov_map);
+    Ai::Alerted {
+        x,
+        y,
+        num_turns: num_turns - 1,
+    }
+}
+
+fn monster_move_towards(
+    monster_id: usize,
+    target_x: i32,
+    target_y: i32,
+    objects: &mut [Object],
+    game: &mut Game,
+    fov_map: &mut FovMap,
+) {
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    let (dx, dy) = step_towards(&objects[monster_id], target_x, target_y);
+    let (dx, dy) = [(dx, dy), (dx, 0), (0, dy)]
+        .iter()
+        .cloned()
+        .find(|&(dx, dy)| {
+            let (x, y) = (monster_x + dx, monster_y + dy);
+            !monster_knows_trap(x, y, &game.map)
+                && !game.map[x as usize][y as usize].terrain.is_hazard()
+        })
+        .unwrap_or((0, 0));
+    let (x, y) = (monster_x + dx, monster_y + dy);
+    if game.map[x as usize][y as usize].door == Some(Door::Closed) {
+        set_door(x, y, Door::Open, &mut game.map, fov_map);
+        if is_visible(x, y, &game.map, fov_map) {
+            game.log.add(
+                format!("The {} opens a door.", objects[monster_id].name),
+                LIGHT_GREY,
+            );
+        }
+    } else {
+        move_by(monster_id, dx, dy, objects, game, fov_map);
+    }
+}
+
 fn ai_confused(
     monster_id: usize,
     objects: &mut [Object],
     game: &mut Game,
+    fov_map: &FovMap,
     previous_ai: Box<Ai>,
     num_turns: i32,
 ) -> Ai {
@@ -793,8 +2112,9 @@ fn ai_confused(
             monster_id,
             rand::thread_rng().gen_range(-1, 2),
             rand::thread_rng().gen_range(-1, 2),
-            &game.map,
             objects,
+            game,
+            fov_map,
         );
         Ai::Confused {
             previous_ai,
@@ -814,6 +2134,16 @@ struct Tile {
     blocked: bool,
     block_sight: bool,
     explored: bool,
+    #[serde(default)]
+    lit: bool,
+    #[serde(default)]
+    light: Color,
+    #[serde(default)]
+    terrain: Terrain,
+    #[serde(default)]
+    door: Option<Door>,
+    #[serde(default)]
+    trap: Option<Trap>,
 }
 
 impl Tile {
@@ -822,6 +2152,11 @@ impl Tile {
             blocked: false,
             block_sight: false,
             explored: false,
+            lit: false,
+            light: BLACK,
+            terrain: Terrain::Floor,
+            door: None,
+            trap: None,
         }
     }
 
@@ -830,26 +2165,459 @@ impl Tile {
             blocked: true,
             block_sight: true,
             explored: false,
+            lit: false,
+            light: BLACK,
+            terrain: Terrain::Wall,
+            door: None,
+            trap: None,
         }
     }
-}
 
-type Map = Vec<Vec<Tile>>;
+    pub fn door(door: Door) -> Self {
+        let mut tile = Tile::empty();
+        tile.set_door(door);
+        tile
+    }
 
-#[derive(Clone, Copy, Debug)]
-struct Rect {
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32,
+    pub fn set_door(&mut self, door: Door) {
+        let closed = door != Door::Open;
+        self.door = Some(door);
+        self.blocked = closed;
+        self.block_sight = closed;
+    }
 }
 
-impl Rect {
-    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
-        Rect {
-            x1: x,
-            y1: y,
-            x2: x + w,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+enum Terrain {
+    #[default]
+    Floor,
+    Wall,
+    ShallowWater,
+    DeepWater,
+    Lava,
+    Chasm,
+    Rubble,
+}
+
+impl Terrain {
+    pub fn movement_cost(self) -> i32 {
+        match self {
+            Terrain::ShallowWater | Terrain::Rubble => 2,
+            Terrain::DeepWater => 3,
+            _ => 1,
+        }
+    }
+
+    pub fn is_hazard(self) -> bool {
+        matches!(self, Terrain::DeepWater | Terrain::Lava | Terrain::Chasm)
+    }
+
+    pub fn swallows_items(self) -> bool {
+        self.is_hazard()
+    }
+
+    pub fn color(self, visible: bool) -> Color {
+        let (light, dark) = match self {
+            Terrain::Floor => (COLOR_LIGHT_GROUND, COLOR_DARK_GROUND),
+            Terrain::Wall => (COLOR_LIGHT_WALL, COLOR_DARK_WALL),
+            Terrain::ShallowWater => (COLOR_LIGHT_SHALLOW_WATER, COLOR_DARK_SHALLOW_WATER),
+            Terrain::DeepWater => (COLOR_LIGHT_DEEP_WATER, COLOR_DARK_DEEP_WATER),
+            Terrain::Lava => (COLOR_LIGHT_LAVA, COLOR_DARK_LAVA),
+            Terrain::Chasm => (COLOR_LIGHT_CHASM, COLOR_DARK_CHASM),
+            Terrain::Rubble => (COLOR_LIGHT_RUBBLE, COLOR_DARK_RUBBLE),
+        };
+        if visible {
+            light
+        } else {
+            dark
+        }
+    }
+
+    pub fn glyph(self) -> Option<char> {
+        match self {
+            Terrain::ShallowWater | Terrain::DeepWater | Terrain::Lava => Some('~'),
+            Terrain::Rubble => Some(':'),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Terrain {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Terrain::Floor => write!(f, "floor"),
+            Terrain::Wall => write!(f, "wall"),
+            Terrain::ShallowWater => write!(f, "shallow water"),
+            Terrain::DeepWater => write!(f, "deep water"),
+            Terrain::Lava => write!(f, "lava"),
+            Terrain::Chasm => write!(f, "chasm"),
+            Terrain::Rubble => write!(f, "rubble"),
+        }
+    }
+}
+
+const LAVA_DAMAGE: i32 = 15;
+const SWIM_DROP_CHANCE: i32 = 10;
+
+fn enter_terrain(id: usize, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) {
+    let (x, y) = objects[id].pos();
+    let seen = id == PLAYER || is_visible(x, y, &game.map, fov_map);
+    let name = objects[id].name.clone();
+    match game.map[x as usize][y as usize].terrain {
+        Terrain::Lava => {
+            if seen {
+                game.log.add(
+                    format!(
+                        "{} is burned by the lava for {} hit points.",
+                        name, LAVA_DAMAGE
+                    ),
+                    ORANGE,
+                );
+            }
+            objects[id].take_damage(LAVA_DAMAGE, game);
+        }
+        Terrain::DeepWater if id == PLAYER => {
+            let loose_items: Vec<_> = game
+                .inventory
+                .iter()
+                .enumerate()
+                .filter(|&(_, item)| item.equipment.is_none_or(|e| !e.equipped))
+                .map(|(inventory_id, _)| inventory_id)
+                .collect();
+            if !loose_items.is_empty() && rand::thread_rng().gen_range(0, 100) < SWIM_DROP_CHANCE {
+                let inventory_id = loose_items[rand::thread_rng().gen_range(0, loose_items.len())];
+                let name = game.inventory[inventory_id].known_name(game);
+                if game.inventory[inventory_id].count > 1 {
+                    game.inventory[inventory_id].count -= 1;
+                } else {
+                    game.inventory.remove(inventory_id);
+                }
+                game.log.add(
+                    format!("Your {} slips away into the deep water!", name),
+                    LIGHT_BLUE,
+                );
+            }
+        }
+        Terrain::Chasm if seen => {
+            game.log.add(format!("{} falls into the chasm!", name), RED);
+        }
+        _ => {}
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Door {
+    Open,
+    Closed,
+    Locked,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+struct Trap {
+    kind: TrapKind,
+    revealed: bool,
+    #[serde(default)]
+    known_to_monsters: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum TrapKind {
+    Dart,
+    Teleport,
+    Alarm,
+    Pit,
+}
+
+impl std::fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            TrapKind::Dart => write!(f, "dart trap"),
+            TrapKind::Teleport => write!(f, "teleport trap"),
+            TrapKind::Alarm => write!(f, "alarm trap"),
+            TrapKind::Pit => write!(f, "pit"),
+        }
+    }
+}
+
+const DART_DAMAGE: i32 = 8;
+const PIT_DAMAGE: i32 = 10;
+const ALARM_NUM_TURNS: i32 = 20;
+
+const SEARCH_RADIUS: i32 = 2;
+const SEARCH_CHANCE: i32 = 75;
+const DETECT_RADIUS: i32 = 3;
+const DETECT_CHANCE: i32 = 10;
+
+const DISARM_BASE_CHANCE: i32 = 50;
+const DISARM_LEVEL_BONUS: i32 = 10;
+const DISARM_MAX_CHANCE: i32 = 95;
+
+fn is_known_trap(x: i32, y: i32, map: &Map) -> bool {
+    map[x as usize][y as usize]
+        .trap
+        .is_some_and(|trap| trap.revealed)
+}
+
+fn monster_knows_trap(x: i32, y: i32, map: &Map) -> bool {
+    map[x as usize][y as usize]
+        .trap
+        .is_some_and(|trap| trap.known_to_monsters)
+}
+
+fn spring_trap(id: usize, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) {
+    let (x, y) = objects[id].pos();
+    let kind = match game.map[x as usize][y as usize].trap {
+        Some(trap) => trap.kind,
+        None => return,
+    };
+    let seen = id == PLAYER || is_visible(x, y, &game.map, fov_map);
+    if let Some(ref mut trap) = game.map[x as usize][y as usize].trap {
+        trap.known_to_monsters = true;
+        if seen {
+            trap.revealed = true;
+        }
+    }
+    let name = objects[id].name.clone();
+    match kind {
+        TrapKind::Dart => {
+            if seen {
+                game.log.add(
+                    format!(
+                        "A dart shoots out and hits {} for {} hit points.",
+                        name, DART_DAMAGE
+                    ),
+                    ORANGE,
+                );
+            }
+            objects[id].take_damage(DART_DAMAGE, game);
+        }
+        TrapKind::Teleport => {
+            let (new_x, new_y) = random_floor_tile(&game.map, objects);
+            objects[id].set_pos(new_x, new_y);
+            if seen {
+                game.log.add(
+                    format!("{} vanishes in a flash of light!", name),
+                    LIGHT_MAGENTA,
+                );
+            }
+        }
+        TrapKind::Alarm => {
+            game.log
+                .add("A loud alarm rings through the level!", YELLOW);
+            for object in objects.iter_mut() {
+                if object.ai == Some(Ai::Basic) {
+                    object.ai = Some(Ai::Alerted {
+                        x,
+                        y,
+                        num_turns: ALARM_NUM_TURNS,
+                    });
+                }
+            }
+        }
+        TrapKind::Pit => {
+            if seen {
+                game.log.add(format!("{} falls into a pit!", name), ORANGE);
+            }
+            objects[id].take_damage(PIT_DAMAGE, game);
+        }
+    }
+}
+
+fn random_floor_tile(map: &Map, objects: &[Object]) -> (i32, i32) {
+    loop {
+        let x = rand::thread_rng().gen_range(0, MAP_WIDTH);
+        let y = rand::thread_rng().gen_range(0, MAP_HEIGHT);
+        let tile = &map[x as usize][y as usize];
+        if !is_blocked(x, y, map, objects) && tile.trap.is_none() && tile.terrain == Terrain::Floor
+        {
+            return (x, y);
+        }
+    }
+}
+
+fn detect_traps(radius: i32, chance: i32, objects: &[Object], game: &mut Game, fov_map: &FovMap) {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    for x in cmp::max(0, player_x - radius)..cmp::min(MAP_WIDTH, player_x + radius + 1) {
+        for y in cmp::max(0, player_y - radius)..cmp::min(MAP_HEIGHT, player_y + radius + 1) {
+            if !is_visible(x, y, &game.map, fov_map) {
+                continue;
+            }
+            if let Some(ref mut trap) = game.map[x as usize][y as usize].trap {
+                if !trap.revealed && rand::thread_rng().gen_range(0, 100) < chance {
+                    trap.revealed = true;
+                    game.log
+                        .add(format!("You find a {}!", trap.kind), LIGHT_CYAN);
+                }
+            }
+        }
+    }
+}
+
+fn disarm_trap(tcod: &mut Tcod, objects: &[Object], game: &mut Game) -> PlayerAction {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let traps: Vec<_> = adjacent_tiles(player_x, player_y)
+        .into_iter()
+        .filter(|&(x, y)| is_known_trap(x, y, &game.map))
+        .collect();
+    let (x, y) = match choose_adjacent_tile(&traps, tcod, objects, game) {
+        Some(tile) => tile,
+        None => {
+            if traps.is_empty() {
+                game.log.add("There is no known trap next to you.", WHITE);
+            }
+            return PlayerAction::DidnTakeTurn;
+        }
+    };
+
+    let kind = game.map[x as usize][y as usize].trap.unwrap().kind;
+    let chance = cmp::min(
+        DISARM_MAX_CHANCE,
+        DISARM_BASE_CHANCE + objects[PLAYER].level * DISARM_LEVEL_BONUS,
+    );
+    if rand::thread_rng().gen_range(0, 100) < chance {
+        game.map[x as usize][y as usize].trap = None;
+        game.log
+            .add(format!("You disarm the {}.", kind), LIGHT_GREEN);
+    } else {
+        game.log
+            .add(format!("You fail to disarm the {}.", kind), RED);
+    }
+    PlayerAction::TookTurn
+}
+
+fn set_door(x: i32, y: i32, door: Door, map: &mut Map, fov_map: &mut FovMap) {
+    map[x as usize][y as usize].set_door(door);
+    update_fov_tile(x, y, map, fov_map);
+}
+
+fn adjacent_tiles(x: i32, y: i32) -> Vec<(i32, i32)> {
+    let mut tiles = vec![];
+    for dx in -1..2 {
+        for dy in -1..2 {
+            let (tile_x, tile_y) = (x + dx, y + dy);
+            if (dx, dy) != (0, 0)
+                && (0..MAP_WIDTH).contains(&tile_x)
+                && (0..MAP_HEIGHT).contains(&tile_y)
+            {
+                tiles.push((tile_x, tile_y));
+            }
+        }
+    }
+    tiles
+}
+
+fn adjacent_doors(x: i32, y: i32, map: &Map) -> Vec<(i32, i32)> {
+    adjacent_tiles(x, y)
+        .into_iter()
+        .filter(|&(x, y)| map[x as usize][y as usize].door.is_some())
+        .collect()
+}
+
+fn choose_adjacent_tile(
+    tiles: &[(i32, i32)],
+    tcod: &mut Tcod,
+    objects: &[Object],
+    game: &mut Game,
+) -> Option<(i32, i32)> {
+    match tiles.len() {
+        0 => None,
+        1 => Some(tiles[0]),
+        _ => {
+            game.log.add(
+                "Press a direction key to choose, or any other to cancel.",
+                LIGHT_CYAN,
+            );
+            render_all(tcod, objects, game, false);
+            tcod.root.flush();
+            let key = tcod.root.wait_for_keypress(true);
+            let (player_x, player_y) = objects[PLAYER].pos();
+            match tcod.keymap.direction(key) {
+                Some((dx, dy)) if tiles.contains(&(player_x + dx, player_y + dy)) => {
+                    Some((player_x + dx, player_y + dy))
+                }
+                _ => {
+                    game.log.add("Cancelled", WHITE);
+                    None
+                }
+            }
+        }
+    }
+}
+
+fn player_open_door(x: i32, y: i32, game: &mut Game, fov_map: &mut FovMap) -> bool {
+    match game.map[x as usize][y as usize].door {
+        Some(Door::Closed) => {
+            set_door(x, y, Door::Open, &mut game.map, fov_map);
+            game.log.add("You open the door.", WHITE);
+            true
+        }
+        Some(Door::Locked) => {
+            let key_id = game
+                .inventory
+                .iter()
+                .position(|item| item.item == Some(Item::Key));
+            if let Some(key_id) = key_id {
+                if game.inventory[key_id].count > 1 {
+                    game.inventory[key_id].count -= 1;
+                } else {
+                    game.inventory.remove(key_id);
+                }
+                set_door(x, y, Door::Open, &mut game.map, fov_map);
+                game.log.add("You unlock the door with a key.", LIGHT_GREEN);
+                true
+            } else {
+                game.log.add("The door is locked.", RED);
+                false
+            }
+        }
+        _ => false,
+    }
+}
+
+fn toggle_door(tcod: &mut Tcod, objects: &[Object], game: &mut Game) -> PlayerAction {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let doors = adjacent_doors(player_x, player_y, &game.map);
+    let (x, y) = match choose_adjacent_tile(&doors, tcod, objects, game) {
+        Some(tile) => tile,
+        None => {
+            if doors.is_empty() {
+                game.log.add("There is no door next to you.", WHITE);
+            }
+            return PlayerAction::DidnTakeTurn;
+        }
+    };
+
+    if game.map[x as usize][y as usize].door == Some(Door::Open) {
+        if objects.iter().any(|object| object.pos() == (x, y)) {
+            game.log.add("Something is blocking the door.", RED);
+            return PlayerAction::DidnTakeTurn;
+        }
+        set_door(x, y, Door::Closed, &mut game.map, &mut tcod.fov);
+        game.log.add("You close the door.", WHITE);
+        PlayerAction::TookTurn
+    } else if player_open_door(x, y, game, &mut tcod.fov) {
+        PlayerAction::TookTurn
+    } else {
+        PlayerAction::DidnTakeTurn
+    }
+}
+
+type Map = Vec<Vec<Tile>>;
+
+#[derive(Clone, Copy, Debug)]
+struct Rect {
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Rect {
+            x1: x,
+            y1: y,
+            x2: x + w,
             y2: y + h,
         }
     }
@@ -875,27 +2643,74 @@ enum PlayerAction {
     Exit,
 }
 
-fn get_equipped_in_slot(slot: Slot, inventory: &[Object]) -> Option<usize> {
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Activity {
+    Explore,
+    Travel(i32, i32),
+    Rest,
+    Run(i32, i32),
+    Repeat(Command, u32),
+}
+
+fn get_equipped_in_slot(slot: Slot, inventory: &[Object]) -> Vec<usize> {
+    let mut equipped = vec![];
     for (inventory_id, item) in inventory.iter().enumerate() {
-        if item
-            .equipment
-            .as_ref()
-            .map_or(false, |e| e.equipped && e.slot == slot)
-        {
-            return Some(inventory_id);
+        if item.equipment.as_ref().is_some_and(|e| e.occupies(slot)) {
+            equipped.push(inventory_id);
         }
     }
-    None
+    equipped
 }
 
-fn move_by(id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [Object]) {
+fn equip_target(equipment: Equipment, inventory: &[Object]) -> (bool, Vec<usize>) {
+    let main_hand = get_equipped_in_slot(Slot::RightHand, inventory);
+    let off_hand = get_equipped_in_slot(Slot::LeftHand, inventory);
+    let is_two_handed = |&id: &usize| inventory[id].equipment.is_some_and(|e| e.two_handed);
+    if equipment.two_handed {
+        let mut displaced = main_hand;
+        displaced.extend(off_hand);
+        displaced.sort();
+        displaced.dedup();
+        return (false, displaced);
+    }
+    match equipment.slot {
+        Slot::RightHand | Slot::LeftHand if main_hand.iter().any(is_two_handed) => {
+            (false, main_hand)
+        }
+        Slot::RightHand if !main_hand.is_empty() && off_hand.is_empty() => (true, vec![]),
+        slot => {
+            let occupied = get_equipped_in_slot(slot, inventory);
+            if occupied.len() >= slot.capacity() {
+                (false, vec![occupied[0]])
+            } else {
+                (false, vec![])
+            }
+        }
+    }
+}
+
+fn move_by(id: usize, dx: i32, dy: i32, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) {
     let (x, y) = objects[id].pos();
-    if !is_blocked(x + dx, y + dy, map, objects) {
-        objects[id].set_pos(x + dx, y + dy);
+    if (dx, dy) == (0, 0) || is_blocked(x + dx, y + dy, &game.map, objects) {
+        return;
+    }
+    let terrain = game.map[(x + dx) as usize][(y + dy) as usize].terrain;
+    if id != PLAYER && terrain == Terrain::Chasm {
+        return;
     }
+    objects[id].set_pos(x + dx, y + dy);
+    objects[id].delay = terrain.movement_cost() - 1;
+    enter_terrain(id, objects, game, fov_map);
+    spring_trap(id, objects, game, fov_map);
 }
 
-fn player_move_or_attack(dx: i32, dy: i32, objects: &mut [Object], game: &mut Game) {
+fn player_move_or_attack(
+    dx: i32,
+    dy: i32,
+    tcod: &mut Tcod,
+    objects: &mut Vec<Object>,
+    game: &mut Game,
+) -> bool {
     let x = objects[PLAYER].x + dx;
     let y = objects[PLAYER].y + dy;
 
@@ -907,9 +2722,30 @@ fn player_move_or_attack(dx: i32, dy: i32, objects: &mut [Object], game: &mut Ga
         Some(target_id) => {
             let (player, target) = mut_two(PLAYER, target_id, objects);
             player.attack(target, game);
+            true
+        }
+        None if game.map[x as usize][y as usize].blocked
+            && game.map[x as usize][y as usize].door.is_some() =>
+        {
+            player_open_door(x, y, game, &mut tcod.fov)
         }
         None => {
-            move_by(PLAYER, dx, dy, &game.map, objects);
+            let terrain = game.map[x as usize][y as usize].terrain;
+            if terrain == Terrain::Lava && !is_blocked(x, y, &game.map, objects) {
+                let question = format!("Really step into the {}?", terrain);
+                if menu(&question, &["Yes", "No"], 24, &mut tcod.root) != Some(0) {
+                    return false;
+                }
+            }
+            move_by(PLAYER, dx, dy, objects, game, &tcod.fov);
+            let (x, y) = objects[PLAYER].pos();
+            let tile = game.map[x as usize][y as usize];
+            let on_pit = tile.trap.is_some_and(|trap| trap.kind == TrapKind::Pit);
+            if (on_pit || tile.terrain == Terrain::Chasm) && objects[PLAYER].alive {
+                game.log.add("You tumble down to the level below!", RED);
+                descend(tcod, objects, game);
+            }
+            true
         }
     }
 }
@@ -953,11 +2789,11 @@ fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, level: u32) {
     let monster_chances = &mut [
         Weighted {
             weight: 80,
-            item: "orc",
+            item: Monster::Orc,
         },
         Weighted {
             weight: troll_chance,
-            item: "troll",
+            item: Monster::Troll,
         },
     ];
     let monster_choice = WeightedChoice::new(monster_chances);
@@ -967,8 +2803,9 @@ fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, level: u32) {
         let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
 
         if !is_blocked(x, y, map, objects) {
-            let mut monster = match monster_choice.ind_sample(&mut rand::thread_rng()) {
-                "orc" => {
+            let kind = monster_choice.ind_sample(&mut rand::thread_rng());
+            let mut monster = match kind {
+                Monster::Orc => {
                     let mut orc = Object::new(x, y, 'o', "orc", DESATURATED_GREEN, true);
                     orc.fighter = Some(Fighter {
                         hp: 20,
@@ -979,9 +2816,21 @@ fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, level: u32) {
                         xp: 35,
                     });
                     orc.ai = Some(Ai::Basic);
+                    if rand::thread_rng().gen_range(0, 100) < ORC_WEAPON_CHANCE {
+                        orc.give_equipment(Item::Sword);
+                    }
+                    if rand::thread_rng().gen_range(0, 100) < ORC_SHIELD_CHANCE {
+                        orc.give_equipment(Item::Shield);
+                    }
+                    if rand::thread_rng().gen_range(0, 100) < MONSTER_TORCH_CHANCE {
+                        orc.light = Some(Light {
+                            radius: MONSTER_TORCH_RADIUS,
+                            color: FLAME,
+                        });
+                    }
                     orc
                 }
-                "troll" => {
+                Monster::Troll => {
                     let mut troll = Object::new(x, y, 'T', "troll", DARKER_GREEN, true);
                     troll.fighter = Some(Fighter {
                         hp: 30,
@@ -992,10 +2841,13 @@ fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, level: u32) {
                         xp: 100,
                     });
                     troll.ai = Some(Ai::Basic);
+                    if rand::thread_rng().gen_range(0, 100) < TROLL_HELMET_CHANCE {
+                        troll.give_equipment(Item::Helmet);
+                    }
                     troll
                 }
-                _ => unreachable!(),
             };
+            monster.monster = Some(kind);
             monster.alive = true;
 
             objects.push(monster);
@@ -1012,145 +2864,772 @@ fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, level: u32) {
     let num_items = rand::thread_rng().gen_range(0, max_items + 1);
     let item_chances = &mut [
         Weighted {
-            weight: 35,
-            item: Item::Heal,
+            weight: 35,
+            item: Item::Heal,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 4,
+                    value: 25,
+                }],
+                level,
+            ),
+            item: Item::Lightning,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 6,
+                    value: 25,
+                }],
+                level,
+            ),
+            item: Item::Fireball,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 2,
+                    value: 10,
+                }],
+                level,
+            ),
+            item: Item::Confuse,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 2,
+                    value: 15,
+                }],
+                level,
+            ),
+            item: Item::Identify,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 4, value: 5 }], level),
+            item: Item::Recharge,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 3, value: 4 }], level),
+            item: Item::WandLightning,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 3, value: 4 }], level),
+            item: Item::WandConfuse,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 3, value: 3 }], level),
+            item: Item::WandDigging,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 4, value: 5 }], level),
+            item: Item::Sword,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 6, value: 5 }], level),
+            item: Item::Greataxe,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 2, value: 5 }], level),
+            item: Item::Bow,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 2,
+                    value: 10,
+                }],
+                level,
+            ),
+            item: Item::Arrow,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 5, value: 4 }], level),
+            item: Item::Crossbow,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 5, value: 8 }], level),
+            item: Item::Bolt,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 8,
+                    value: 15,
+                }],
+                level,
+            ),
+            item: Item::Shield,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 2, value: 5 }], level),
+            item: Item::Helmet,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 3, value: 5 }], level),
+            item: Item::Boots,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 3, value: 5 }], level),
+            item: Item::Cloak,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 6, value: 5 }], level),
+            item: Item::BodyArmor,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 5, value: 3 }], level),
+            item: Item::Ring,
+        },
+        Weighted {
+            weight: from_dungeon_level(&[Transition { level: 7, value: 3 }], level),
+            item: Item::Amulet,
+        },
+    ];
+    let item_choice = WeightedChoice::new(item_chances);
+
+    for _ in 0..num_items {
+        let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
+        let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
+
+        if !is_blocked(x, y, map, objects) {
+            let mut item = create_item(item_choice.ind_sample(&mut rand::thread_rng()), x, y);
+            if item.item.map(Item::category) == Some(ItemCategory::Ammo) {
+                item.count = rand::thread_rng().gen_range(AMMO_MIN_STACK, AMMO_MAX_STACK + 1);
+            }
+            objects.push(item);
+        }
+    }
+}
+
+fn place_traps(room: Rect, map: &mut Map, level: u32) {
+    let trap_chance = from_dungeon_level(
+        &[
+            Transition {
+                level: 1,
+                value: 20,
+            },
+            Transition {
+                level: 3,
+                value: 30,
+            },
+            Transition {
+                level: 6,
+                value: 40,
+            },
+        ],
+        level,
+    );
+    if rand::thread_rng().gen_range(0, 100) >= trap_chance {
+        return;
+    }
+
+    let trap_chances = &mut [
+        Weighted {
+            weight: 40,
+            item: TrapKind::Dart,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 2,
+                    value: 20,
+                }],
+                level,
+            ),
+            item: TrapKind::Teleport,
+        },
+        Weighted {
+            weight: 20,
+            item: TrapKind::Alarm,
+        },
+        Weighted {
+            weight: from_dungeon_level(
+                &[Transition {
+                    level: 3,
+                    value: 15,
+                }],
+                level,
+            ),
+            item: TrapKind::Pit,
+        },
+    ];
+    let trap_choice = WeightedChoice::new(trap_chances);
+
+    let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
+    let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
+    if (x, y) != room.center() {
+        map[x as usize][y as usize].trap = Some(Trap {
+            kind: trap_choice.ind_sample(&mut rand::thread_rng()),
+            revealed: false,
+            known_to_monsters: false,
+        });
+    }
+}
+
+fn create_item(item: Item, x: i32, y: i32) -> Object {
+    match item {
+        Item::Heal => {
+            let mut object = Object::new(x, y, '!', "healing potion", VIOLET, false);
+            object.item = Some(Item::Heal);
+            object.weight = 2;
+            object
+        }
+        Item::Lightning => {
+            let mut object =
+                Object::new(x, y, '#', "scroll of lightning bolt", LIGHT_YELLOW, false);
+            object.item = Some(Item::Lightning);
+            object.weight = 1;
+            object
+        }
+        Item::Fireball => {
+            let mut object = Object::new(x, y, '#', "scroll of fireball", LIGHT_YELLOW, false);
+            object.item = Some(Item::Fireball);
+            object.weight = 1;
+            object
+        }
+        Item::Confuse => {
+            let mut object = Object::new(x, y, '#', "scroll of confusion", LIGHT_YELLOW, false);
+            object.item = Some(Item::Confuse);
+            object.weight = 1;
+            object
+        }
+        Item::Identify => {
+            let mut object = Object::new(x, y, '#', "scroll of identify", LIGHT_YELLOW, false);
+            object.item = Some(Item::Identify);
+            object.weight = 1;
+            object
+        }
+        Item::Recharge => {
+            let mut object = Object::new(x, y, '#', "scroll of recharging", LIGHT_YELLOW, false);
+            object.item = Some(Item::Recharge);
+            object.weight = 1;
+            object
+        }
+        Item::WandLightning => {
+            let mut object = Object::new(x, y, '/', "wand of lightning", LIGHT_BLUE, false);
+            object.item = Some(Item::WandLightning);
+            object.weight = 2;
+            object.charges =
+                Some(rand::thread_rng().gen_range(WAND_MIN_CHARGES, WAND_MAX_CHARGES + 1));
+            object
+        }
+        Item::WandConfuse => {
+            let mut object = Object::new(x, y, '/', "wand of confusion", LIGHT_GREEN, false);
+            object.item = Some(Item::WandConfuse);
+            object.weight = 2;
+            object.charges =
+                Some(rand::thread_rng().gen_range(WAND_MIN_CHARGES, WAND_MAX_CHARGES + 1));
+            object
+        }
+        Item::WandDigging => {
+            let mut object = Object::new(x, y, '/', "wand of digging", DARK_AMBER, false);
+            object.item = Some(Item::WandDigging);
+            object.weight = 2;
+            object.charges =
+                Some(rand::thread_rng().gen_range(WAND_MIN_CHARGES, WAND_MAX_CHARGES + 1));
+            object
+        }
+        Item::Sword => {
+            let mut object = Object::new(x, y, '/', "sword", SKY, false);
+            object.item = Some(Item::Sword);
+            object.weight = 10;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::RightHand,
+                max_hp_bonus: 0,
+                power_bonus: 3,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Greataxe => {
+            let mut object = Object::new(x, y, '/', "greataxe", DARK_SKY, false);
+            object.item = Some(Item::Greataxe);
+            object.weight = 18;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: true,
+                off_hand: false,
+                slot: Slot::RightHand,
+                max_hp_bonus: 0,
+                power_bonus: 6,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Bow => {
+            let mut object = Object::new(x, y, '}', "bow", SEPIA, false);
+            object.item = Some(Item::Bow);
+            object.weight = 5;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: true,
+                off_hand: false,
+                slot: Slot::RightHand,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Crossbow => {
+            let mut object = Object::new(x, y, '}', "crossbow", DARK_SEPIA, false);
+            object.item = Some(Item::Crossbow);
+            object.weight = 8;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: true,
+                off_hand: false,
+                slot: Slot::RightHand,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Arrow => {
+            let mut object = Object::new(x, y, '|', "arrow", LIGHT_SEPIA, false);
+            object.item = Some(Item::Arrow);
+            object.weight = 1;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Quiver,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Bolt => {
+            let mut object = Object::new(x, y, '|', "bolt", LIGHT_GREY, false);
+            object.item = Some(Item::Bolt);
+            object.weight = 1;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Quiver,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Shield => {
+            let mut object = Object::new(x, y, '[', "shield", DARKER_ORANGE, false);
+            object.item = Some(Item::Shield);
+            object.weight = 12;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::LeftHand,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 1,
+            });
+            object
+        }
+        Item::Helmet => {
+            let mut object = Object::new(x, y, '[', "helmet", LIGHT_GREY, false);
+            object.item = Some(Item::Helmet);
+            object.weight = 4;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Head,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 1,
+            });
+            object
+        }
+        Item::BodyArmor => {
+            let mut object = Object::new(x, y, '[', "chain mail", GREY, false);
+            object.item = Some(Item::BodyArmor);
+            object.weight = 25;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Body,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 3,
+            });
+            object
+        }
+        Item::Cloak => {
+            let mut object = Object::new(x, y, '(', "cloak", DARK_GREEN, false);
+            object.item = Some(Item::Cloak);
+            object.weight = 3;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Cloak,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 1,
+            });
+            object
+        }
+        Item::Boots => {
+            let mut object = Object::new(x, y, '[', "boots", DARK_SEPIA, false);
+            object.item = Some(Item::Boots);
+            object.weight = 3;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Feet,
+                max_hp_bonus: 0,
+                power_bonus: 0,
+                defense_bonus: 1,
+            });
+            object
+        }
+        Item::Amulet => {
+            let mut object = Object::new(x, y, '"', "amulet of vitality", LIGHT_AMBER, false);
+            object.item = Some(Item::Amulet);
+            object.weight = 1;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Neck,
+                max_hp_bonus: 20,
+                power_bonus: 0,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Ring => {
+            let mut object = Object::new(x, y, '=', "ring of strength", GOLD, false);
+            object.item = Some(Item::Ring);
+            object.weight = 1;
+            object.equipment = Some(Equipment {
+                equipped: false,
+                two_handed: false,
+                off_hand: false,
+                slot: Slot::Ring,
+                max_hp_bonus: 0,
+                power_bonus: 1,
+                defense_bonus: 0,
+            });
+            object
+        }
+        Item::Key => {
+            let mut object = Object::new(x, y, '-', "key", COLOR_LOCKED_DOOR, false);
+            object.item = Some(Item::Key);
+            object.weight = 1;
+            object
+        }
+    }
+}
+
+fn step_towards(object: &Object, target_x: i32, target_y: i32) -> (i32, i32) {
+    let dx = target_x - object.x;
+    let dy = target_y - object.y;
+    let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
+
+    let dx = (dx as f32 / distance).round() as i32;
+    let dy = (dy as f32 / distance).round() as i32;
+    (dx, dy)
+}
+
+fn create_room(room: Rect, map: &mut Map) {
+    for x in (room.x1 + 1)..room.x2 {
+        for y in (room.y1 + 1)..room.y2 {
+            map[x as usize][y as usize] = Tile::empty();
+        }
+    }
+}
+
+fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
+    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
+    for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+fn is_doorway(x: i32, y: i32, map: &Map) -> bool {
+    let blocked = |x: i32, y: i32| map[x as usize][y as usize].blocked;
+    if x <= 0 || x >= MAP_WIDTH - 1 || y <= 0 || y >= MAP_HEIGHT - 1 || blocked(x, y) {
+        return false;
+    }
+    let horizontal =
+        blocked(x - 1, y) && blocked(x + 1, y) && !blocked(x, y - 1) && !blocked(x, y + 1);
+    let vertical =
+        blocked(x, y - 1) && blocked(x, y + 1) && !blocked(x - 1, y) && !blocked(x + 1, y);
+    horizontal || vertical
+}
+
+fn reachable_tiles(start: (i32, i32), map: &Map) -> Vec<(i32, i32)> {
+    let mut visited = vec![vec![false; MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+    visited[start.0 as usize][start.1 as usize] = true;
+    let mut reachable = vec![start];
+    let mut index = 0;
+    while index < reachable.len() {
+        let (x, y) = reachable[index];
+        index += 1;
+        for dx in -1..2 {
+            for dy in -1..2 {
+                let (next_x, next_y) = (x + dx, y + dy);
+                if !(0..MAP_WIDTH).contains(&next_x) || !(0..MAP_HEIGHT).contains(&next_y) {
+                    continue;
+                }
+                let tile = &map[next_x as usize][next_y as usize];
+                let passable = !tile.blocked || tile.door == Some(Door::Closed);
+                if passable && !visited[next_x as usize][next_y as usize] {
+                    visited[next_x as usize][next_y as usize] = true;
+                    reachable.push((next_x, next_y));
+                }
+            }
+        }
+    }
+    reachable
+}
+
+fn find_path<F: Fn(i32, i32) -> bool>(
+    start: (i32, i32),
+    map: &Map,
+    is_goal: F,
+) -> Option<Vec<(i32, i32)>> {
+    let mut came_from = vec![vec![None; MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+    came_from[start.0 as usize][start.1 as usize] = Some(start);
+    let mut frontier = vec![start];
+    let mut index = 0;
+    while index < frontier.len() {
+        let (x, y) = frontier[index];
+        index += 1;
+        if is_goal(x, y) {
+            let mut path = vec![];
+            let mut position = (x, y);
+            while position != start {
+                path.push(position);
+                position = came_from[position.0 as usize][position.1 as usize].unwrap();
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for dx in -1..2 {
+            for dy in -1..2 {
+                let (next_x, next_y) = (x + dx, y + dy);
+                if !(0..MAP_WIDTH).contains(&next_x) || !(0..MAP_HEIGHT).contains(&next_y) {
+                    continue;
+                }
+                let tile = &map[next_x as usize][next_y as usize];
+                let passable = (!tile.blocked || tile.door == Some(Door::Closed))
+                    && tile.explored
+                    && !tile.terrain.is_hazard()
+                    && !is_known_trap(next_x, next_y, map);
+                if passable && came_from[next_x as usize][next_y as usize].is_none() {
+                    came_from[next_x as usize][next_y as usize] = Some((x, y));
+                    frontier.push((next_x, next_y));
+                }
+            }
+        }
+    }
+    None
+}
+
+fn place_doors(rooms: &[Rect], map: &mut Map, objects: &mut Vec<Object>, level: u32) {
+    let locked_chance = from_dungeon_level(
+        &[
+            Transition {
+                level: 2,
+                value: 10,
+            },
+            Transition {
+                level: 5,
+                value: 20,
+            },
+        ],
+        level,
+    ) as i32;
+
+    let mut locked_doors = 0;
+    for room in rooms {
+        let mut walls = vec![];
+        for x in room.x1..(room.x2 + 1) {
+            walls.push((x, room.y1));
+            walls.push((x, room.y2));
+        }
+        for y in (room.y1 + 1)..room.y2 {
+            walls.push((room.x1, y));
+            walls.push((room.x2, y));
+        }
+
+        for (x, y) in walls {
+            if !is_doorway(x, y, map) || rand::thread_rng().gen_range(0, 100) >= DOOR_CHANCE {
+                continue;
+            }
+            if rand::thread_rng().gen_range(0, 100) < locked_chance {
+                map[x as usize][y as usize] = Tile::door(Door::Locked);
+                locked_doors += 1;
+            } else {
+                map[x as usize][y as usize] = Tile::door(Door::Closed);
+            }
+        }
+    }
+
+    let reachable: Vec<_> = reachable_tiles(objects[PLAYER].pos(), map)
+        .into_iter()
+        .filter(|&(x, y)| {
+            map[x as usize][y as usize].door.is_none()
+                && !objects.iter().any(|object| object.pos() == (x, y))
+        })
+        .collect();
+    for _ in 0..locked_doors {
+        if reachable.is_empty() {
+            break;
+        }
+        let (x, y) = reachable[rand::thread_rng().gen_range(0, reachable.len())];
+        objects.push(create_item(Item::Key, x, y));
+    }
+}
+
+fn place_terrain(rooms: &[Rect], map: &mut Map, objects: &[Object], level: u32) {
+    let terrain_chances = &mut [
+        Weighted {
+            weight: 30,
+            item: Terrain::ShallowWater,
         },
         Weighted {
-            weight: from_dungeon_level(
-                &[Transition {
-                    level: 4,
-                    value: 25,
-                }],
-                level,
-            ),
-            item: Item::Lightning,
+            weight: 30,
+            item: Terrain::Rubble,
         },
         Weighted {
             weight: from_dungeon_level(
                 &[Transition {
-                    level: 6,
-                    value: 25,
+                    level: 2,
+                    value: 15,
                 }],
                 level,
             ),
-            item: Item::Fireball,
+            item: Terrain::DeepWater,
         },
         Weighted {
             weight: from_dungeon_level(
                 &[Transition {
-                    level: 2,
+                    level: 3,
                     value: 10,
                 }],
                 level,
             ),
-            item: Item::Confuse,
-        },
-        Weighted {
-            weight: from_dungeon_level(&[Transition { level: 4, value: 5 }], level),
-            item: Item::Sword,
+            item: Terrain::Chasm,
         },
         Weighted {
             weight: from_dungeon_level(
                 &[Transition {
-                    level: 8,
+                    level: 4,
                     value: 15,
                 }],
                 level,
             ),
-            item: Item::Shield,
+            item: Terrain::Lava,
         },
     ];
-    let item_choice = WeightedChoice::new(item_chances);
-
-    for _ in 0..num_items {
-        let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
-        let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
+    let terrain_choice = WeightedChoice::new(terrain_chances);
 
-        if !is_blocked(x, y, map, objects) {
-            let item = match item_choice.ind_sample(&mut rand::thread_rng()) {
-                Item::Heal => {
-                    let mut object = Object::new(x, y, '!', "healing potion", VIOLET, false);
-                    object.item = Some(Item::Heal);
-                    object
-                }
-                Item::Lightning => {
-                    let mut object =
-                        Object::new(x, y, '#', "scroll of lightning bolt", LIGHT_YELLOW, false);
-                    object.item = Some(Item::Lightning);
-                    object
-                }
-                Item::Fireball => {
-                    let mut object =
-                        Object::new(x, y, '#', "scroll of fireball", LIGHT_YELLOW, false);
-                    object.item = Some(Item::Fireball);
-                    object
-                }
-                Item::Confuse => {
-                    let mut object =
-                        Object::new(x, y, '#', "scroll of confusion", LIGHT_YELLOW, false);
-                    object.item = Some(Item::Confuse);
-                    object
-                }
-                Item::Sword => {
-                    let mut object = Object::new(x, y, '/', "sword", SKY, false);
-                    object.item = Some(Item::Sword);
-                    object.equipment = Some(Equipment {
-                        equipped: false,
-                        slot: Slot::RightHand,
-                        max_hp_bonus: 0,
-                        power_bonus: 3,
-                        defense_bonus: 0,
-                    });
-                    object
-                }
-                Item::Shield => {
-                    let mut object = Object::new(x, y, '[', "shield", DARKER_ORANGE, false);
-                    object.item = Some(Item::Shield);
-                    object.equipment = Some(Equipment {
-                        equipped: false,
-                        slot: Slot::LeftHand,
-                        max_hp_bonus: 0,
-                        power_bonus: 0,
-                        defense_bonus: 1,
-                    });
-                    object
+    for room in rooms {
+        if rand::thread_rng().gen_range(0, 100) >= TERRAIN_CHANCE {
+            continue;
+        }
+        let terrain = terrain_choice.ind_sample(&mut rand::thread_rng());
+        let w = rand::thread_rng().gen_range(2, (room.x2 - room.x1) / 2 + 1);
+        let h = rand::thread_rng().gen_range(2, (room.y2 - room.y1) / 2 + 1);
+        let x1 = rand::thread_rng().gen_range(room.x1 + 1, room.x2 - w + 1);
+        let y1 = rand::thread_rng().gen_range(room.y1 + 1, room.y2 - h + 1);
+        let (center_x, center_y) = room.center();
+
+        for x in x1..(x1 + w) {
+            for y in y1..(y1 + h) {
+                let tile = &mut map[x as usize][y as usize];
+                let occupied = objects.iter().any(|object| object.pos() == (x, y));
+                if x == center_x
+                    || y == center_y
+                    || occupied
+                    || tile.blocked
+                    || tile.door.is_some()
+                    || tile.trap.is_some()
+                {
+                    continue;
                 }
-            };
-            objects.push(item);
+                tile.terrain = terrain;
+            }
         }
     }
 }
 
-fn move_towards(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut [Object]) {
-    let dx = target_x - objects[id].x;
-    let dy = target_y - objects[id].y;
-    let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
-
-    let dx = (dx as f32 / distance).round() as i32;
-    let dy = (dy as f32 / distance).round() as i32;
-    move_by(id, dx, dy, map, objects);
-}
+fn place_lights(rooms: &[Rect], map: &mut Map, objects: &mut Vec<Object>, level: u32) {
+    let lit_room_chance = from_dungeon_level(
+        &[
+            Transition {
+                level: 1,
+                value: 70,
+            },
+            Transition {
+                level: 3,
+                value: 50,
+            },
+            Transition {
+                level: 5,
+                value: 30,
+            },
+        ],
+        level,
+    );
+    let torch_chance = from_dungeon_level(
+        &[
+            Transition {
+                level: 1,
+                value: 20,
+            },
+            Transition {
+                level: 4,
+                value: 35,
+            },
+        ],
+        level,
+    );
 
-fn create_room(room: Rect, map: &mut Map) {
-    for x in (room.x1 + 1)..room.x2 {
-        for y in (room.y1 + 1)..room.y2 {
-            map[x as usize][y as usize] = Tile::empty();
+    for room in rooms {
+        if rand::thread_rng().gen_range(0, 100) < lit_room_chance {
+            for x in room.x1..(room.x2 + 1) {
+                for y in room.y1..(room.y2 + 1) {
+                    map[x as usize][y as usize].lit = true;
+                }
+            }
+        } else if rand::thread_rng().gen_range(0, 100) < torch_chance {
+            let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
+            let y = if rand::random() { room.y1 } else { room.y2 };
+            if map[x as usize][y as usize].door.is_none() && map[x as usize][y as usize].blocked {
+                let mut torch = Object::new(x, y, '*', "torch", FLAME, false);
+                torch.light = Some(Light {
+                    radius: WALL_TORCH_RADIUS,
+                    color: TORCH_COLOR,
+                });
+                objects.push(torch);
+            }
         }
     }
 }
 
-fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
-    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
-    }
-}
-
-fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
-    for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
-    }
-}
-
 fn make_map(objects: &mut Vec<Object>, level: u32) -> Map {
     let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
 
@@ -1189,6 +3668,7 @@ fn make_map(objects: &mut Vec<Object>, level: u32) -> Map {
             }
             rooms.push(new_room);
             place_objects(new_room, &map, objects, level);
+            place_traps(new_room, &mut map, level);
         }
     }
 
@@ -1199,40 +3679,98 @@ fn make_map(objects: &mut Vec<Object>, level: u32) -> Map {
     stairs.always_visible = true;
     objects.push(stairs);
 
+    place_doors(&rooms, &mut map, objects, level);
+    place_terrain(&rooms, &mut map, objects, level);
+    place_lights(&rooms, &mut map, objects, level);
+
     map
 }
 
+fn is_visible(x: i32, y: i32, map: &Map, fov_map: &FovMap) -> bool {
+    fov_map.is_in_fov(x, y) && map[x as usize][y as usize].light != BLACK
+}
+
+fn compute_lighting(objects: &[Object], map: &mut Map, fov_map: &FovMap) {
+    for column in map.iter_mut() {
+        for tile in column.iter_mut() {
+            tile.light = if tile.lit { COLOR_ROOM_LIGHT } else { BLACK };
+        }
+    }
+
+    let mut light_map = fov_map.clone();
+    for object in objects {
+        let light = match object.light {
+            Some(light) => light,
+            None => continue,
+        };
+        light_map.compute_fov(object.x, object.y, light.radius, FOV_LIGHT_WALLS, FOV_ALGO);
+        for x in 0..MAP_WIDTH {
+            for y in 0..MAP_HEIGHT {
+                let falloff = 1.0 - object.distance(x, y) / (light.radius + 1) as f32;
+                if light_map.is_in_fov(x, y) && falloff > 0.0 {
+                    let tile = &mut map[x as usize][y as usize];
+                    tile.light = tile.light + light.color * falloff;
+                }
+            }
+        }
+    }
+}
+
+fn blend_light(dark_color: Color, light_color: Color, light: Color) -> Color {
+    let intensity = cmp::max(light.r, cmp::max(light.g, light.b));
+    if intensity == 0 {
+        return dark_color;
+    }
+    let tint = light * (255.0 / intensity as f32);
+    lerp(dark_color, light_color * tint, intensity as f32 / 255.0)
+}
+
 fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recompute: bool) {
     tcod.con.clear();
 
     if fov_recompute {
         let player = &objects[PLAYER];
         tcod.fov
-            .compute_fov(player.x, player.y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+            .compute_fov(player.x, player.y, FOV_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+        compute_lighting(objects, &mut game.map, &tcod.fov);
     }
 
     let mut to_draw: Vec<_> = objects
         .iter()
         .filter(|o| {
-            tcod.fov.is_in_fov(o.x, o.y)
+            is_visible(o.x, o.y, &game.map, &tcod.fov)
                 || (o.always_visible && game.map[o.x as usize][o.y as usize].explored)
         })
         .collect();
     to_draw.sort_by(|o1, o2| o1.blocks.cmp(&o2.blocks));
 
+    let map = &game.map;
+    game.remembered_items
+        .retain(|&(x, y, _, _)| !is_visible(x, y, map, &tcod.fov));
     for object in &to_draw {
-        object.draw(&mut tcod.con);
+        if object.item.is_some() && is_visible(object.x, object.y, &game.map, &tcod.fov) {
+            game.remembered_items
+                .push((object.x, object.y, object.char, object.color));
+        }
     }
 
     for y in 0..MAP_HEIGHT {
         for x in 0..MAP_WIDTH {
-            let visible = tcod.fov.is_in_fov(x, y);
+            let visible = is_visible(x, y, &game.map, &tcod.fov);
             let wall = game.map[x as usize][y as usize].block_sight;
-            let color = match (visible, wall) {
-                (false, true) => COLOR_DARK_WALL,
-                (false, false) => COLOR_DARK_GROUND,
-                (true, true) => COLOR_LIGHT_WALL,
-                (true, false) => COLOR_LIGHT_GROUND,
+            let door = game.map[x as usize][y as usize].door;
+            let trap = game.map[x as usize][y as usize].trap;
+            let terrain = game.map[x as usize][y as usize].terrain;
+            let light = game.map[x as usize][y as usize].light;
+            let (dark_color, light_color) = if wall {
+                (COLOR_DARK_WALL, COLOR_LIGHT_WALL)
+            } else {
+                (terrain.color(false), terrain.color(true))
+            };
+            let color = if visible {
+                blend_light(dark_color, light_color, light)
+            } else {
+                dark_color
             };
             let explored = &mut game.map[x as usize][y as usize].explored;
             if visible {
@@ -1241,14 +3779,49 @@ fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recomput
             if *explored {
                 tcod.con
                     .set_char_background(x, y, color, BackgroundFlag::Set);
+                if let Some(glyph) = terrain.glyph() {
+                    tcod.con.set_default_foreground(lerp(color, WHITE, 0.3));
+                    tcod.con.put_char(x, y, glyph, BackgroundFlag::None);
+                }
+                if let Some(door) = door {
+                    let (char, door_color) = match door {
+                        Door::Open => ('\'', COLOR_DOOR),
+                        Door::Closed => ('+', COLOR_DOOR),
+                        Door::Locked => ('+', COLOR_LOCKED_DOOR),
+                    };
+                    let door_color = if visible { door_color } else { COLOR_DARK_DOOR };
+                    tcod.con.set_default_foreground(door_color);
+                    tcod.con.put_char(x, y, char, BackgroundFlag::None);
+                }
+                if let Some(trap) = trap.filter(|trap| trap.revealed) {
+                    let trap_color = match trap.kind {
+                        TrapKind::Dart => LIGHT_GREY,
+                        TrapKind::Teleport => LIGHT_MAGENTA,
+                        TrapKind::Alarm => YELLOW,
+                        TrapKind::Pit => DARK_ORANGE,
+                    };
+                    let trap_color = if visible { trap_color } else { DARK_GREY };
+                    tcod.con.set_default_foreground(trap_color);
+                    tcod.con.put_char(x, y, '^', BackgroundFlag::None);
+                }
             }
         }
     }
 
+    for object in &to_draw {
+        object.draw(&mut tcod.con);
+    }
+
+    if let Some((x, y)) = tcod.cursor {
+        tcod.con
+            .set_char_background(x, y, COLOR_CURSOR, BackgroundFlag::Set);
+    }
+
+    tcod.camera = camera_position(objects[PLAYER].x, objects[PLAYER].y);
     blit(
         &tcod.con,
-        (0, 0),
-        (MAP_WIDTH, MAP_HEIGHT),
+        tcod.camera,
+        (CAMERA_WIDTH, CAMERA_HEIGHT),
         &mut tcod.root,
         (0, 0),
         1.0,
@@ -1278,16 +3851,49 @@ fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recomput
         TextAlignment::Left,
         format!("Dungeon level: {}", game.dungeon_level),
     );
+    if let Some(count) = tcod.count {
+        tcod.panel.print_ex(
+            1,
+            4,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            format!("Count: {}", count),
+        );
+    }
+    let burden = objects[PLAYER].burden(game);
+    if burden != Burden::Unburdened {
+        tcod.panel.set_default_foreground(burden.color());
+        tcod.panel.print_ex(
+            1,
+            5,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            format!("{}", burden).to_uppercase(),
+        );
+    }
 
     let mut y = MSG_HEIGHT as i32;
-    for &(ref msg, color) in game.log.iter().rev() {
-        let msg_height = tcod.panel.get_height_rect(MSG_X, y, MSG_WIDTH, 0, msg);
+    let mut messages = game.log.iter().rev().peekable();
+    while let Some(message) = messages.next() {
+        let msg = message.text();
+        let msg_height = tcod.panel.get_height_rect(MSG_X, y, MSG_WIDTH, 0, &msg);
         y -= msg_height;
         if y < 0 {
             break;
         }
+        let color = if message.turn + 1 < game.log.turn {
+            message.color * 0.5
+        } else {
+            message.color
+        };
         tcod.panel.set_default_foreground(color);
-        tcod.panel.print_rect(MSG_X, y, MSG_WIDTH, 0, msg);
+        tcod.panel.print_rect(MSG_X, y, MSG_WIDTH, 0, &msg);
+        let turn_starts = messages
+            .peek()
+            .is_none_or(|previous| previous.turn != message.turn);
+        if turn_starts {
+            tcod.panel.put_char(MSG_X - 1, y, '>', BackgroundFlag::None);
+        }
     }
 
     tcod.panel.set_default_foreground(LIGHT_GREY);
@@ -1296,7 +3902,10 @@ fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recomput
         0,
         BackgroundFlag::None,
         TextAlignment::Left,
-        get_names_under_mouse(tcod.mouse, objects, &tcod.fov),
+        match tcod.cursor {
+            Some((x, y)) => get_names_at(x, y, objects, game, &tcod.fov),
+            None => get_names_under_mouse(tcod.mouse, tcod.camera, objects, game, &tcod.fov),
+        },
     );
 
     blit(
@@ -1310,15 +3919,49 @@ fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game, fov_recomput
     );
 }
 
-fn get_names_under_mouse(mouse: Mouse, objects: &[Object], fov_map: &FovMap) -> String {
+fn mouse_map_pos(mouse: Mouse, camera: (i32, i32)) -> Option<(i32, i32)> {
     let (x, y) = (mouse.cx as i32, mouse.cy as i32);
+    if x < CAMERA_WIDTH && y < CAMERA_HEIGHT {
+        Some((x + camera.0, y + camera.1))
+    } else {
+        None
+    }
+}
+
+fn camera_position(target_x: i32, target_y: i32) -> (i32, i32) {
+    let x = target_x - CAMERA_WIDTH / 2;
+    let y = target_y - CAMERA_HEIGHT / 2;
+    (
+        x.clamp(0, MAP_WIDTH - CAMERA_WIDTH),
+        y.clamp(0, MAP_HEIGHT - CAMERA_HEIGHT),
+    )
+}
+
+fn get_names_under_mouse(
+    mouse: Mouse,
+    camera: (i32, i32),
+    objects: &[Object],
+    game: &Game,
+    fov_map: &FovMap,
+) -> String {
+    match mouse_map_pos(mouse, camera) {
+        Some((x, y)) => get_names_at(x, y, objects, game, fov_map),
+        None => String::new(),
+    }
+}
 
-    let names = objects
+fn get_names_at(x: i32, y: i32, objects: &[Object], game: &Game, fov_map: &FovMap) -> String {
+    let map = &game.map;
+    let mut names = objects
         .iter()
-        .filter(|obj| obj.pos() == (x, y) && fov_map.is_in_fov(obj.x, obj.y))
-        .map(|obj| obj.name.clone())
+        .filter(|obj| obj.pos() == (x, y) && is_visible(obj.x, obj.y, map, fov_map))
+        .map(|obj| obj.display_name(game))
         .collect::<Vec<_>>();
 
+    if is_visible(x, y, map, fov_map) && is_known_trap(x, y, map) {
+        names.push(map[x as usize][y as usize].trap.unwrap().kind.to_string());
+    }
+
     names.join(", ")
 }
 
@@ -1328,45 +3971,94 @@ fn handle_keys(
     objects: &mut Vec<Object>,
     game: &mut Game,
 ) -> PlayerAction {
+    use Command::*;
     use PlayerAction::*;
 
-    let player_alive = objects[PLAYER].alive;
-
-    match (key, player_alive) {
-        (Key { code: Up, .. }, true) | (Key { code: NumPad8, .. }, true) => {
-            player_move_or_attack(0, -1, objects, game);
-            TookTurn
+    if key.code == Enter && key.alt {
+        let fullscreen = tcod.root.is_fullscreen();
+        tcod.root.set_fullscreen(!fullscreen);
+        return DidnTakeTurn;
+    }
+    if key.code == Escape {
+        if tcod.count.take().is_some() {
+            return DidnTakeTurn;
         }
-        (Key { code: Down, .. }, true) | (Key { code: NumPad2, .. }, true) => {
-            player_move_or_attack(0, 1, objects, game);
-            TookTurn
+        return Exit;
+    }
+
+    let player_alive = objects[PLAYER].alive;
+    if tcod.mouse.lbutton_pressed {
+        tcod.mouse.lbutton_pressed = false;
+        if let Some((x, y)) = mouse_map_pos(tcod.mouse, tcod.camera) {
+            if player_alive && game.map[x as usize][y as usize].explored {
+                tcod.activity = Some(Activity::Travel(x, y));
+            }
         }
-        (Key { code: Left, .. }, true) | (Key { code: NumPad4, .. }, true) => {
-            player_move_or_attack(-1, 0, objects, game);
-            TookTurn
+        return DidnTakeTurn;
+    }
+
+    let command = match tcod.keymap.command(key) {
+        Some(command) => command,
+        None => {
+            if let Some(digit) = key_name(key).and_then(|name| name.parse::<u32>().ok()) {
+                let count = tcod.count.unwrap_or(0) * 10 + digit;
+                tcod.count = Some(cmp::min(count, MAX_REPEAT_COUNT));
+            }
+            return DidnTakeTurn;
         }
-        (Key { code: Right, .. }, true) | (Key { code: NumPad6, .. }, true) => {
-            player_move_or_attack(1, 0, objects, game);
-            TookTurn
+    };
+    match tcod.count.take() {
+        Some(count) if count > 1 && command.repeatable() && player_alive => {
+            let command = if command == Rest { Wait } else { command };
+            tcod.activity = Some(Activity::Repeat(command, count));
+            DidnTakeTurn
         }
-        (Key { code: Home, .. }, true) | (Key { code: NumPad7, .. }, true) => {
-            player_move_or_attack(-1, -1, objects, game);
-            TookTurn
+        _ => do_command(command, tcod, objects, game),
+    }
+}
+
+fn do_command(
+    command: Command,
+    tcod: &mut Tcod,
+    objects: &mut Vec<Object>,
+    game: &mut Game,
+) -> PlayerAction {
+    use Command::*;
+    use PlayerAction::*;
+
+    let player_alive = objects[PLAYER].alive;
+    if let (Some((dx, dy)), true) = (command.direction(), player_alive) {
+        if player_move_or_attack(dx, dy, tcod, objects, game) {
+            return TookTurn;
         }
-        (Key { code: PageUp, .. }, true) | (Key { code: NumPad9, .. }, true) => {
-            player_move_or_attack(1, -1, objects, game);
-            TookTurn
+        return DidnTakeTurn;
+    }
+    if let (Some((dx, dy)), true) = (command.run_direction(), player_alive) {
+        tcod.activity = Some(Activity::Run(dx, dy));
+        return DidnTakeTurn;
+    }
+
+    match (command, player_alive) {
+        (Wait, true) => TookTurn,
+        (Rest, true) => {
+            tcod.activity = Some(Activity::Rest);
+            DidnTakeTurn
         }
-        (Key { code: End, .. }, true) | (Key { code: NumPad1, .. }, true) => {
-            player_move_or_attack(-1, 1, objects, game);
-            TookTurn
+        (AutoExplore, true) => {
+            tcod.activity = Some(Activity::Explore);
+            DidnTakeTurn
         }
-        (Key { code: PageDown, .. }, true) | (Key { code: NumPad3, .. }, true) => {
-            player_move_or_attack(1, 1, objects, game);
-            TookTurn
+        (TravelToStairs, true) => {
+            let stairs = objects.iter().find(|object| {
+                object.name == "stairs" && game.map[object.x as usize][object.y as usize].explored
+            });
+            match stairs {
+                Some(stairs) => tcod.activity = Some(Activity::Travel(stairs.x, stairs.y)),
+                None => game.log.add("You haven't found the stairs yet.", WHITE),
+            }
+            DidnTakeTurn
         }
-        (Key { code: NumPad5, .. }, true) => TookTurn,
-        (Key { printable: 'g', .. }, true) => {
+        (PickUp, true) => {
             let item_id = objects
                 .iter()
                 .position(|object| object.pos() == objects[PLAYER].pos() && object.item.is_some());
@@ -1375,9 +4067,12 @@ fn handle_keys(
             }
             DidnTakeTurn
         }
-        (Key { printable: 'd', .. }, true) => {
+        (Throw, true) => throw_item(tcod, objects, game),
+        (Fire, true) => fire_launcher(tcod, objects, game),
+        (Disarm, true) => disarm_trap(tcod, objects, game),
+        (Drop, true) => {
             let inventory_index = inventory_menu(
-                &game.inventory,
+                game,
                 "Press the key next to an item to drop it, or any other to cancel.\n'",
                 &mut tcod.root,
             );
@@ -1386,9 +4081,9 @@ fn handle_keys(
             }
             DidnTakeTurn
         }
-        (Key { printable: 'i', .. }, true) => {
+        (Inventory, true) => {
             let inventory_index = inventory_menu(
-                &game.inventory,
+                game,
                 "Press the key next to an item to use it, or any other to cancel.\n",
                 &mut tcod.root,
             );
@@ -1403,7 +4098,29 @@ fn handle_keys(
                 DidnTakeTurn
             }
         }
-        (Key { printable: 's', .. }, true) => {
+        (Equipment, true) => {
+            equipment_screen(&objects[PLAYER], game, &mut tcod.root);
+            DidnTakeTurn
+        }
+        (ToggleDoor, true) => toggle_door(tcod, objects, game),
+        (MessageHistory, _) => {
+            message_history(&game.log, &mut tcod.root);
+            DidnTakeTurn
+        }
+        (Look, true) => {
+            look(tcod, objects, game);
+            DidnTakeTurn
+        }
+        (OverviewMap, true) => {
+            overview_map(objects, game, &mut tcod.root);
+            DidnTakeTurn
+        }
+        (Search, true) => {
+            game.log.add("You search your surroundings.", WHITE);
+            detect_traps(SEARCH_RADIUS, SEARCH_CHANCE, objects, game, &tcod.fov);
+            TookTurn
+        }
+        (Descend, true) => {
             let player_on_stairs = objects
                 .iter()
                 .any(|object| object.pos() == objects[PLAYER].pos() && object.name == "stairs");
@@ -1412,7 +4129,7 @@ fn handle_keys(
             }
             DidnTakeTurn
         }
-        (Key { printable: 'c', .. }, true) => {
+        (Character, true) => {
             let player = &objects[PLAYER];
             let level = player.level;
             let level_up_xp = LEVEL_UP_BASE + player.level * LEVEL_UP_FACTOR;
@@ -1426,35 +4143,457 @@ Experience to level up: {}
 
 Maximum HP: {}
 Attack: {}
-Defense: {}",
+Defense: {}
+
+Carrying: {}/{}
+Burden: {}",
                     level,
                     fighter.xp,
                     level_up_xp,
                     player.max_hp(game),
                     player.power(game),
-                    player.defense(game)
+                    player.defense(game),
+                    inventory_weight(&game.inventory),
+                    player.carry_capacity(),
+                    player.burden(game)
                 );
                 msgbox(&msg, CHARACTER_SCREEN_WIDTH, &mut tcod.root);
             }
             DidnTakeTurn
         }
-        (
-            Key {
-                code: Enter,
-                alt: true,
-                ..
-            },
-            _,
-        ) => {
-            let fullscreen = tcod.root.is_fullscreen();
-            tcod.root.set_fullscreen(!fullscreen);
+        (KeyBindings, _) => {
+            key_bindings_screen(&tcod.keymap, &mut tcod.root);
             DidnTakeTurn
         }
-        (Key { code: Escape, .. }, _) => Exit,
         _ => DidnTakeTurn,
     }
 }
 
+const KEYMAP_FILE: &str = "keymap.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum Command {
+    MoveNorth,
+    MoveSouth,
+    MoveWest,
+    MoveEast,
+    MoveNorthWest,
+    MoveNorthEast,
+    MoveSouthWest,
+    MoveSouthEast,
+    RunNorth,
+    RunSouth,
+    RunWest,
+    RunEast,
+    RunNorthWest,
+    RunNorthEast,
+    RunSouthWest,
+    RunSouthEast,
+    Wait,
+    Rest,
+    PickUp,
+    Drop,
+    Throw,
+    Fire,
+    Disarm,
+    Inventory,
+    Equipment,
+    ToggleDoor,
+    MessageHistory,
+    Look,
+    OverviewMap,
+    Search,
+    AutoExplore,
+    TravelToStairs,
+    Descend,
+    Character,
+    KeyBindings,
+}
+
+impl Command {
+    fn all() -> &'static [Command] {
+        use Command::*;
+        &[
+            MoveNorth,
+            MoveSouth,
+            MoveWest,
+            MoveEast,
+            MoveNorthWest,
+            MoveNorthEast,
+            MoveSouthWest,
+            MoveSouthEast,
+            RunNorth,
+            RunSouth,
+            RunWest,
+            RunEast,
+            RunNorthWest,
+            RunNorthEast,
+            RunSouthWest,
+            RunSouthEast,
+            Wait,
+            Rest,
+            PickUp,
+            Drop,
+            Throw,
+            Fire,
+            Disarm,
+            Inventory,
+            Equipment,
+            ToggleDoor,
+            MessageHistory,
+            Look,
+            OverviewMap,
+            Search,
+            AutoExplore,
+            TravelToStairs,
+            Descend,
+            Character,
+            KeyBindings,
+        ]
+    }
+
+    fn direction(self) -> Option<(i32, i32)> {
+        use Command::*;
+        match self {
+            MoveNorth => Some((0, -1)),
+            MoveSouth => Some((0, 1)),
+            MoveWest => Some((-1, 0)),
+            MoveEast => Some((1, 0)),
+            MoveNorthWest => Some((-1, -1)),
+            MoveNorthEast => Some((1, -1)),
+            MoveSouthWest => Some((-1, 1)),
+            MoveSouthEast => Some((1, 1)),
+            _ => None,
+        }
+    }
+
+    fn repeatable(self) -> bool {
+        match self {
+            Command::Wait | Command::Rest | Command::Search => true,
+            command => command.direction().is_some(),
+        }
+    }
+
+    fn run_direction(self) -> Option<(i32, i32)> {
+        use Command::*;
+        match self {
+            RunNorth => Some((0, -1)),
+            RunSouth => Some((0, 1)),
+            RunWest => Some((-1, 0)),
+            RunEast => Some((1, 0)),
+            RunNorthWest => Some((-1, -1)),
+            RunNorthEast => Some((1, -1)),
+            RunSouthWest => Some((-1, 1)),
+            RunSouthEast => Some((1, 1)),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use Command::*;
+        let description = match *self {
+            MoveNorth => "move north",
+            MoveSouth => "move south",
+            MoveWest => "move west",
+            MoveEast => "move east",
+            MoveNorthWest => "move north-west",
+            MoveNorthEast => "move north-east",
+            MoveSouthWest => "move south-west",
+            MoveSouthEast => "move south-east",
+            RunNorth => "run north",
+            RunSouth => "run south",
+            RunWest => "run west",
+            RunEast => "run east",
+            RunNorthWest => "run north-west",
+            RunNorthEast => "run north-east",
+            RunSouthWest => "run south-west",
+            RunSouthEast => "run south-east",
+            Wait => "wait a turn",
+            Rest => "rest until healed",
+            PickUp => "pick up an item",
+            Drop => "drop an item",
+            Throw => "throw an item",
+            Fire => "fire a ranged weapon",
+            Disarm => "disarm a trap",
+            Inventory => "use an item",
+            Equipment => "equipment worn",
+            ToggleDoor => "open or close a door",
+            MessageHistory => "message history",
+            Look => "look around",
+            OverviewMap => "overview map",
+            Search => "search for traps",
+            AutoExplore => "explore the level",
+            TravelToStairs => "travel to the stairs",
+            Descend => "take the stairs",
+            Character => "character information",
+            KeyBindings => "list key bindings",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+enum KeyPreset {
+    Numpad,
+    Vi,
+    Wasd,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeymapConfig {
+    preset: KeyPreset,
+    #[serde(default)]
+    bindings: HashMap<String, Command>,
+}
+
+struct Keymap {
+    preset: KeyPreset,
+    bindings: Vec<(String, Command)>,
+}
+
+impl Keymap {
+    fn new(preset: KeyPreset) -> Self {
+        use Command::*;
+        let arrows = [
+            ("Up", MoveNorth),
+            ("Down", MoveSouth),
+            ("Left", MoveWest),
+            ("Right", MoveEast),
+            ("Shift+Up", RunNorth),
+            ("Shift+Down", RunSouth),
+            ("Shift+Left", RunWest),
+            ("Shift+Right", RunEast),
+        ];
+        let preset_bindings: &[(&str, Command)] = match preset {
+            KeyPreset::Numpad => &[
+                ("NumPad8", MoveNorth),
+                ("NumPad2", MoveSouth),
+                ("NumPad4", MoveWest),
+                ("NumPad6", MoveEast),
+                ("Home", MoveNorthWest),
+                ("NumPad7", MoveNorthWest),
+                ("PageUp", MoveNorthEast),
+                ("NumPad9", MoveNorthEast),
+                ("End", MoveSouthWest),
+                ("NumPad1", MoveSouthWest),
+                ("PageDown", MoveSouthEast),
+                ("NumPad3", MoveSouthEast),
+                ("Shift+NumPad8", RunNorth),
+                ("Shift+NumPad2", RunSouth),
+                ("Shift+NumPad4", RunWest),
+                ("Shift+NumPad6", RunEast),
+                ("Shift+Home", RunNorthWest),
+                ("Shift+NumPad7", RunNorthWest),
+                ("Shift+PageUp", RunNorthEast),
+                ("Shift+NumPad9", RunNorthEast),
+                ("Shift+End", RunSouthWest),
+                ("Shift+NumPad1", RunSouthWest),
+                ("Shift+PageDown", RunSouthEast),
+                ("Shift+NumPad3", RunSouthEast),
+                ("NumPad5", Wait),
+                ("z", Rest),
+                ("g", PickUp),
+                ("d", Drop),
+                ("t", Throw),
+                ("f", Fire),
+                ("D", Disarm),
+                ("i", Inventory),
+                ("E", Equipment),
+                ("o", ToggleDoor),
+                ("p", MessageHistory),
+                ("l", Look),
+                ("m", OverviewMap),
+                ("x", Search),
+                ("e", AutoExplore),
+                ("S", TravelToStairs),
+                ("s", Descend),
+                ("c", Character),
+            ],
+            KeyPreset::Vi => &[
+                ("k", MoveNorth),
+                ("j", MoveSouth),
+                ("h", MoveWest),
+                ("l", MoveEast),
+                ("y", MoveNorthWest),
+                ("u", MoveNorthEast),
+                ("b", MoveSouthWest),
+                ("n", MoveSouthEast),
+                ("K", RunNorth),
+                ("J", RunSouth),
+                ("H", RunWest),
+                ("L", RunEast),
+                ("Y", RunNorthWest),
+                ("U", RunNorthEast),
+                ("B", RunSouthWest),
+                ("N", RunSouthEast),
+                (".", Wait),
+                ("z", Rest),
+                ("g", PickUp),
+                ("d", Drop),
+                ("t", Throw),
+                ("f", Fire),
+                ("D", Disarm),
+                ("i", Inventory),
+                ("E", Equipment),
+                ("o", ToggleDoor),
+                ("p", MessageHistory),
+                (";", Look),
+                ("m", OverviewMap),
+                ("x", Search),
+                ("e", AutoExplore),
+                ("S", TravelToStairs),
+                ("s", Descend),
+                ("c", Character),
+            ],
+            KeyPreset::Wasd => &[
+                ("w", MoveNorth),
+                ("s", MoveSouth),
+                ("a", MoveWest),
+                ("d", MoveEast),
+                ("q", MoveNorthWest),
+                ("e", MoveNorthEast),
+                ("z", MoveSouthWest),
+                ("c", MoveSouthEast),
+                ("W", RunNorth),
+                ("S", RunSouth),
+                ("A", RunWest),
+                ("G", RunEast),
+                ("Q", RunNorthWest),
+                ("E", RunNorthEast),
+                ("Z", RunSouthWest),
+                ("V", RunSouthEast),
+                ("Spacebar", Wait),
+                ("Shift+Spacebar", Rest),
+                ("g", PickUp),
+                ("f", Drop),
+                ("T", Throw),
+                ("F", Fire),
+                ("D", Disarm),
+                ("i", Inventory),
+                ("I", Equipment),
+                ("o", ToggleDoor),
+                ("p", MessageHistory),
+                ("l", Look),
+                ("m", OverviewMap),
+                ("x", Search),
+                ("v", AutoExplore),
+                ("R", TravelToStairs),
+                ("r", Descend),
+                ("C", Character),
+            ],
+        };
+        let mut keymap = Keymap {
+            preset,
+            bindings: vec![],
+        };
+        for &(key, command) in arrows.iter().chain(preset_bindings) {
+            keymap.bind(key.into(), command);
+        }
+        keymap.bind("F1".into(), KeyBindings);
+        keymap
+    }
+
+    fn bind(&mut self, key: String, command: Command) {
+        self.bindings.retain(|(bound_key, _)| *bound_key != key);
+        self.bindings.push((key, command));
+    }
+
+    fn command(&self, key: Key) -> Option<Command> {
+        let name = key_name(key)?;
+        self.bindings
+            .iter()
+            .find(|(bound_key, _)| *bound_key == name)
+            .map(|&(_, command)| command)
+    }
+
+    fn direction(&self, key: Key) -> Option<(i32, i32)> {
+        self.command(key).and_then(Command::direction)
+    }
+
+    fn keys(&self, command: Command) -> Vec<&str> {
+        self.bindings
+            .iter()
+            .filter(|(_, bound_command)| *bound_command == command)
+            .map(|(key, _)| key.as_str())
+            .collect()
+    }
+}
+
+fn key_name(key: Key) -> Option<String> {
+    match key.code {
+        NoKey | Shift | Control | Alt | Text => None,
+        Char | Number0 | Number1 | Number2 | Number3 | Number4 | Number5 | Number6 | Number7
+        | Number8 | Number9 => {
+            if key.shift && key.printable.is_alphabetic() {
+                Some(key.printable.to_ascii_uppercase().to_string())
+            } else {
+                Some(key.printable.to_string())
+            }
+        }
+        code if key.shift => Some(format!("Shift+{:?}", code)),
+        code => Some(format!("{:?}", code)),
+    }
+}
+
+fn load_keymap() -> Result<Keymap, Box<dyn Error>> {
+    let mut json_config = String::new();
+    let mut file = File::open(KEYMAP_FILE)?;
+    file.read_to_string(&mut json_config)?;
+    let config = serde_json::from_str::<KeymapConfig>(&json_config)?;
+    let mut keymap = Keymap::new(config.preset);
+    for (key, command) in config.bindings {
+        keymap.bind(key, command);
+    }
+    Ok(keymap)
+}
+
+fn key_bindings_screen(keymap: &Keymap, root: &mut Root) {
+    let mut window = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    window.set_default_foreground(WHITE);
+    window.print_ex(
+        0,
+        0,
+        BackgroundFlag::None,
+        TextAlignment::Left,
+        format!(
+            "Key bindings ({:?} preset, edit {} to change). Press any key to close.",
+            keymap.preset, KEYMAP_FILE
+        ),
+    );
+    for (index, &command) in Command::all().iter().enumerate() {
+        let y = index as i32 + 2;
+        window.set_default_foreground(LIGHT_GREY);
+        window.print_ex(
+            0,
+            y,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            command.to_string(),
+        );
+        window.set_default_foreground(WHITE);
+        window.print_ex(
+            25,
+            y,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            keymap.keys(command).join(", "),
+        );
+    }
+    blit(
+        &window,
+        (0, 0),
+        (SCREEN_WIDTH, SCREEN_HEIGHT),
+        root,
+        (0, 0),
+        1.0,
+        1.0,
+    );
+    root.flush();
+    root.wait_for_keypress(true);
+}
+
 fn render_bar(
     panel: &mut Offscreen,
     x: i32,
@@ -1539,34 +4678,228 @@ fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root)
 }
 
 const INVENTORY_WIDTH: i32 = 50;
+const INVENTORY_PAGE_HEIGHT: usize = 26;
+
+fn overview_map(objects: &[Object], game: &Game, root: &mut Root) {
+    let scale = cmp::max(
+        (MAP_WIDTH + SCREEN_WIDTH - 1) / SCREEN_WIDTH,
+        (MAP_HEIGHT + SCREEN_HEIGHT - 2) / (SCREEN_HEIGHT - 1),
+    );
+    let width = (MAP_WIDTH + scale - 1) / scale;
+    let height = (MAP_HEIGHT + scale - 1) / scale + 1;
+
+    let mut window = Offscreen::new(width, height);
+    window.set_default_foreground(WHITE);
+    window.print_ex(
+        width / 2,
+        0,
+        BackgroundFlag::None,
+        TextAlignment::Center,
+        format!("Dungeon level {} (press any key)", game.dungeon_level),
+    );
+
+    for cell_x in 0..width {
+        for cell_y in 0..(height - 1) {
+            let mut explored = false;
+            let mut floor = false;
+            for x in (cell_x * scale)..cmp::min(MAP_WIDTH, (cell_x + 1) * scale) {
+                for y in (cell_y * scale)..cmp::min(MAP_HEIGHT, (cell_y + 1) * scale) {
+                    let tile = &game.map[x as usize][y as usize];
+                    if tile.explored {
+                        explored = true;
+                        floor = floor || !tile.block_sight;
+                    }
+                }
+            }
+            if explored {
+                let color = if floor {
+                    COLOR_DARK_GROUND
+                } else {
+                    COLOR_DARK_WALL
+                };
+                window.set_char_background(cell_x, cell_y + 1, color, BackgroundFlag::Set);
+            }
+        }
+    }
+
+    for &(x, y, char, color) in &game.remembered_items {
+        window.set_default_foreground(color);
+        window.put_char(x / scale, y / scale + 1, char, BackgroundFlag::None);
+    }
+    let stairs = objects.iter().filter(|object| object.name == "stairs");
+    for object in stairs.chain(std::iter::once(&objects[PLAYER])) {
+        if game.map[object.x as usize][object.y as usize].explored {
+            window.set_default_foreground(object.color);
+            window.put_char(
+                object.x / scale,
+                object.y / scale + 1,
+                object.char,
+                BackgroundFlag::None,
+            );
+        }
+    }
+
+    let x = SCREEN_WIDTH / 2 - width / 2;
+    let y = SCREEN_HEIGHT / 2 - height / 2;
+    blit(&window, (0, 0), (width, height), root, (x, y), 1.0, 1.0);
+
+    root.flush();
+    root.wait_for_keypress(true);
+}
 
-fn inventory_menu(inventory: &[Object], header: &str, root: &mut Root) -> Option<usize> {
-    let options = if inventory.len() == 0 {
-        vec!["Inventory is empty".into()]
+fn inventory_menu(game: &Game, header: &str, root: &mut Root) -> Option<usize> {
+    let inventory = &game.inventory;
+    let header_height = if header.is_empty() {
+        0
     } else {
-        inventory
-            .iter()
-            .map(|item| match item.equipment {
-                Some(equipment) if equipment.equipped => {
-                    format!("{} (on {})", item.name, equipment.slot)
+        root.get_height_rect(0, 0, INVENTORY_WIDTH, SCREEN_HEIGHT, header)
+    };
+    let mut page = 0;
+    let mut sort_by_name = false;
+    loop {
+        let mut items: Vec<_> = inventory.iter().collect();
+        items.sort_by(|a, b| {
+            let category = |item: &Object| item.item.map(Item::category);
+            category(a).cmp(&category(b)).then_with(|| {
+                if sort_by_name {
+                    a.known_name(game).cmp(&b.known_name(game))
+                } else {
+                    a.letter.cmp(&b.letter)
                 }
-                _ => item.name.clone(),
             })
-            .collect()
-    };
+        });
+
+        let mut lines = vec![];
+        let mut category = None;
+        for item in items {
+            let item_category = item.item.map(Item::category);
+            if item_category != category {
+                category = item_category;
+                if let Some(category) = category {
+                    lines.push((LIGHT_GREY, category.to_string()));
+                }
+            }
+            let name = match item.equipment {
+                Some(equipment) if equipment.equipped => {
+                    format!("{} (on {})", item.name, equipment.held_on())
+                }
+                _ => item.display_name(game),
+            };
+            lines.push((WHITE, format!("({}) {}", item.letter.unwrap_or('?'), name)));
+        }
+        if lines.is_empty() {
+            lines.push((WHITE, "Inventory is empty".into()));
+        }
 
-    let inventory_index = menu(header, &options, INVENTORY_WIDTH, root);
+        let page_count = lines.len().div_ceil(INVENTORY_PAGE_HEIGHT);
+        page = cmp::min(page, page_count - 1);
+        let height = header_height + cmp::min(lines.len(), INVENTORY_PAGE_HEIGHT) as i32 + 2;
 
-    if inventory.len() > 0 {
-        inventory_index
-    } else {
-        None
+        let mut window = Offscreen::new(INVENTORY_WIDTH, height);
+        window.set_default_foreground(WHITE);
+        window.print_rect_ex(
+            0,
+            0,
+            INVENTORY_WIDTH,
+            height,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            header,
+        );
+        let shown = lines.iter().skip(page * INVENTORY_PAGE_HEIGHT);
+        for (index, &(color, ref text)) in shown.take(INVENTORY_PAGE_HEIGHT).enumerate() {
+            window.set_default_foreground(color);
+            window.print_ex(
+                0,
+                header_height + index as i32,
+                BackgroundFlag::None,
+                TextAlignment::Left,
+                text,
+            );
+        }
+        window.set_default_foreground(LIGHT_GREY);
+        window.print_ex(
+            0,
+            height - 1,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            format!(
+                "Page {}/{}, PgUp/PgDn: turn, Tab: sort by {}",
+                page + 1,
+                page_count,
+                if sort_by_name { "letter" } else { "name" }
+            ),
+        );
+
+        let x = SCREEN_WIDTH / 2 - INVENTORY_WIDTH / 2;
+        let y = SCREEN_HEIGHT / 2 - height / 2;
+        blit(
+            &window,
+            (0, 0),
+            (INVENTORY_WIDTH, height),
+            root,
+            (x, y),
+            1.0,
+            0.7,
+        );
+        root.flush();
+
+        let key = root.wait_for_keypress(true);
+        match key {
+            Key { code: PageUp, .. } | Key { code: NumPad9, .. } | Key { code: Left, .. } => {
+                page = page.saturating_sub(1)
+            }
+            Key { code: PageDown, .. } | Key { code: NumPad3, .. } | Key { code: Right, .. } => {
+                page += 1
+            }
+            Key { code: Tab, .. } => sort_by_name = !sort_by_name,
+            Key { code: Shift, .. } | Key { code: Control, .. } | Key { code: Alt, .. } => {}
+            _ if key.printable.is_alphabetic() => {
+                let letter = if key.shift {
+                    key.printable.to_ascii_uppercase()
+                } else {
+                    key.printable
+                };
+                return inventory
+                    .iter()
+                    .position(|item| item.letter == Some(letter));
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn equipment_screen(player: &Object, game: &Game, root: &mut Root) {
+    let mut text = String::from("Equipment worn\n\n");
+    for &slot in Slot::all() {
+        let equipped = get_equipped_in_slot(slot, &game.inventory);
+        for index in 0..slot.capacity() {
+            let name = equipped.get(index).map_or("-".into(), |&inventory_id| {
+                game.inventory[inventory_id].name.clone()
+            });
+            text.push_str(&format!("{:>10}: {}\n", slot.to_string(), name));
+        }
     }
+    let equipment = player.get_all_equipped(game);
+    text.push_str(&format!(
+        "\nTotal: {:+} HP, {:+} attack, {:+} defense",
+        equipment.iter().map(|e| e.max_hp_bonus).sum::<i32>(),
+        equipment
+            .iter()
+            .map(|e| e.effective_power_bonus())
+            .sum::<i32>(),
+        equipment.iter().map(|e| e.defense_bonus).sum::<i32>()
+    ));
+    msgbox(&text, INVENTORY_WIDTH, root);
 }
 
 fn new_game(tcod: &mut Tcod) -> (Vec<Object>, Game) {
     let mut player = Object::new(0, 0, '@', "player", WHITE, true);
     player.alive = true;
+    player.light = Some(Light {
+        radius: TORCH_RADIUS,
+        color: TORCH_COLOR,
+    });
     player.fighter = Some(Fighter {
         hp: 100,
         base_max_hp: 100,
@@ -1580,20 +4913,28 @@ fn new_game(tcod: &mut Tcod) -> (Vec<Object>, Game) {
 
     let mut game = Game {
         map: make_map(&mut objects, 1),
-        log: vec![],
+        log: Messages::new(),
         inventory: vec![],
         dungeon_level: 1,
+        turn: 0,
+        appearances: random_appearances(),
+        identified: vec![],
+        remembered_items: vec![],
     };
 
     let mut dagger = Object::new(0, 0, '-', "dagger", SKY, false);
     dagger.item = Some(Item::Sword);
+    dagger.weight = 4;
     dagger.equipment = Some(Equipment {
         equipped: true,
-        slot: Slot::LeftHand,
+        two_handed: false,
+        off_hand: false,
+        slot: Slot::RightHand,
         max_hp_bonus: 0,
         defense_bonus: 0,
         power_bonus: 2,
     });
+    dagger.letter = inventory_letter(&dagger, &game.inventory);
     game.inventory.push(dagger);
 
     initialise_fov(&game.map, tcod);
@@ -1609,50 +4950,284 @@ fn new_game(tcod: &mut Tcod) -> (Vec<Object>, Game) {
 fn initialise_fov(map: &Map, tcod: &mut Tcod) {
     for y in 0..MAP_HEIGHT {
         for x in 0..MAP_WIDTH {
-            tcod.fov.set(
-                x,
-                y,
-                !map[x as usize][y as usize].block_sight,
-                !map[x as usize][y as usize].blocked,
-            );
+            update_fov_tile(x, y, map, &mut tcod.fov);
         }
     }
     tcod.con.clear();
 }
 
+fn update_fov_tile(x: i32, y: i32, map: &Map, fov_map: &mut FovMap) {
+    fov_map.set(
+        x,
+        y,
+        !map[x as usize][y as usize].block_sight,
+        !map[x as usize][y as usize].blocked,
+    );
+}
+
 fn play_game(objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) {
     let mut previous_player_position = (-1, -1);
-    let mut key = Default::default();
+    let mut previous_player_action = PlayerAction::DidnTakeTurn;
+    let mut previous_player_hp = 0;
     while !tcod.root.window_closed() {
         tcod.con.clear();
 
-        match input::check_for_event(input::MOUSE | input::KEY_PRESS) {
-            Some((_, Event::Mouse(m))) => tcod.mouse = m,
-            Some((_, Event::Key(k))) => key = k,
-            _ => key = Default::default(),
-        }
+        let key = match input::check_for_event(input::MOUSE | input::KEY_PRESS) {
+            Some((_, Event::Mouse(m))) => {
+                tcod.mouse = m;
+                Default::default()
+            }
+            Some((_, Event::Key(k))) => k,
+            _ => Default::default(),
+        };
 
-        let fov_recompute = previous_player_position != (objects[PLAYER].x, objects[PLAYER].y);
+        let fov_recompute = previous_player_position != (objects[PLAYER].x, objects[PLAYER].y)
+            || previous_player_action == PlayerAction::TookTurn;
         render_all(tcod, objects, game, fov_recompute);
 
         tcod.root.flush();
         level_up(objects, game, tcod);
 
         previous_player_position = objects[PLAYER].pos();
-        let player_action = handle_keys(key, tcod, objects, game);
+        let player_hp = objects[PLAYER].fighter.map_or(0, |f| f.hp);
+        let newly_seen = notice_monsters(objects, game, tcod);
+        let danger = game.log.take_danger();
+        let player_action = match tcod.activity {
+            Some(activity) => {
+                let hurt = player_hp < previous_player_hp && !repeating_attack(activity, objects);
+                if key.code != NoKey
+                    || activity_interrupted(hurt, danger, newly_seen, objects, game)
+                {
+                    tcod.activity = None;
+                    PlayerAction::DidnTakeTurn
+                } else {
+                    match activity {
+                        Activity::Explore => explore(tcod, objects, game),
+                        Activity::Travel(x, y) => travel(x, y, tcod, objects, game),
+                        Activity::Rest => rest(tcod, objects, game),
+                        Activity::Run(dx, dy) => run(dx, dy, tcod, objects, game),
+                        Activity::Repeat(command, count) => {
+                            tcod.activity = if count > 1 {
+                                Some(Activity::Repeat(command, count - 1))
+                            } else {
+                                None
+                            };
+                            do_command(command, tcod, objects, game)
+                        }
+                    }
+                }
+            }
+            None => handle_keys(key, tcod, objects, game),
+        };
+        previous_player_hp = player_hp;
         if player_action == PlayerAction::Exit {
             save_game(objects, game).unwrap();
             break;
         }
 
         if objects[PLAYER].alive && player_action != PlayerAction::DidnTakeTurn {
-            for id in 0..objects.len() {
-                if objects[id].ai.is_some() {
-                    ai_take_turn(id, objects, game, &tcod.fov);
+            detect_traps(DETECT_RADIUS, DETECT_CHANCE, objects, game, &tcod.fov);
+            let turns = 1 + objects[PLAYER].delay;
+            objects[PLAYER].delay = 0;
+            for _ in 0..turns {
+                for id in 0..objects.len() {
+                    if objects[id].ai.is_some() {
+                        ai_take_turn(id, objects, game, &mut tcod.fov);
+                    }
                 }
             }
+            drop_carried_items(objects);
+            game.turn += 1;
+            if game.turn % REGEN_INTERVAL == 0 && objects[PLAYER].alive {
+                objects[PLAYER].heal(1, game);
+            }
+            game.log.next_turn();
+        }
+        previous_player_action = player_action;
+    }
+}
+
+const AUTO_EXPLORE_PICKUP: bool = true;
+const REGEN_INTERVAL: u32 = 10;
+const MAX_REPEAT_COUNT: u32 = 999;
+
+fn drop_carried_items(objects: &mut Vec<Object>) {
+    for id in 0..objects.len() {
+        if !objects[id].alive && !objects[id].inventory.is_empty() {
+            let items: Vec<_> = objects[id].inventory.drain(..).collect();
+            objects.extend(items);
+        }
+    }
+}
+
+fn repeating_attack(activity: Activity, objects: &[Object]) -> bool {
+    let (x, y) = objects[PLAYER].pos();
+    match activity {
+        Activity::Repeat(command, _) => command.direction().is_some_and(|(dx, dy)| {
+            objects
+                .iter()
+                .any(|object| object.ai.is_some() && object.pos() == (x + dx, y + dy))
+        }),
+        _ => false,
+    }
+}
+
+fn notice_monsters(objects: &mut [Object], game: &Game, tcod: &Tcod) -> Option<usize> {
+    let mut newly_seen = None;
+    for (id, object) in objects.iter_mut().enumerate() {
+        let visible = object.ai.is_some() && is_visible(object.x, object.y, &game.map, &tcod.fov);
+        if visible && !object.noticed && newly_seen.is_none() {
+            newly_seen = Some(id);
+        }
+        object.noticed = visible;
+    }
+    newly_seen
+}
+
+fn activity_interrupted(
+    hurt: bool,
+    danger: bool,
+    newly_seen: Option<usize>,
+    objects: &[Object],
+    game: &mut Game,
+) -> bool {
+    if !objects[PLAYER].alive {
+        return true;
+    }
+    if hurt {
+        game.log.add("You stop, wounded.", RED);
+        return true;
+    }
+    if danger {
+        return true;
+    }
+    if let Some(id) = newly_seen {
+        game.log.add(
+            format!("You stop, {} comes into view.", objects[id].name),
+            RED,
+        );
+        return true;
+    }
+    false
+}
+
+fn explore(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) -> PlayerAction {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let map = &game.map;
+    let unexplored_neighbour = |x: i32, y: i32| {
+        adjacent_tiles(x, y)
+            .iter()
+            .any(|&(x, y)| !map[x as usize][y as usize].explored)
+    };
+    let path = find_path((player_x, player_y), map, |x, y| {
+        (x, y) != (player_x, player_y) && unexplored_neighbour(x, y)
+    });
+    let (x, y) = match path {
+        Some(path) => path[0],
+        None => {
+            game.log.add("There is nothing left to explore.", WHITE);
+            tcod.activity = None;
+            return PlayerAction::DidnTakeTurn;
+        }
+    };
+    if !player_move_or_attack(x - player_x, y - player_y, tcod, objects, game) {
+        tcod.activity = None;
+        return PlayerAction::DidnTakeTurn;
+    }
+    if AUTO_EXPLORE_PICKUP {
+        let item_id = objects
+            .iter()
+            .position(|object| object.pos() == objects[PLAYER].pos() && object.item.is_some());
+        if let Some(item_id) = item_id {
+            pick_item_up(item_id, objects, game);
+        }
+    }
+    PlayerAction::TookTurn
+}
+
+fn travel(
+    x: i32,
+    y: i32,
+    tcod: &mut Tcod,
+    objects: &mut Vec<Object>,
+    game: &mut Game,
+) -> PlayerAction {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let path = find_path((player_x, player_y), &game.map, |path_x, path_y| {
+        (path_x, path_y) == (x, y)
+    });
+    let (step_x, step_y) = match path.as_ref().and_then(|path| path.first()) {
+        Some(&step) => step,
+        None => {
+            if path.is_none() {
+                game.log.add("You don't know a way there.", WHITE);
+            }
+            tcod.activity = None;
+            return PlayerAction::DidnTakeTurn;
         }
+    };
+    if !player_move_or_attack(step_x - player_x, step_y - player_y, tcod, objects, game) {
+        tcod.activity = None;
+        return PlayerAction::DidnTakeTurn;
+    }
+    if objects[PLAYER].pos() == (x, y) {
+        tcod.activity = None;
+    }
+    PlayerAction::TookTurn
+}
+
+fn rest(tcod: &mut Tcod, objects: &mut [Object], game: &mut Game) -> PlayerAction {
+    let hp = objects[PLAYER].fighter.map_or(0, |f| f.hp);
+    if hp >= objects[PLAYER].max_hp(game) {
+        game.log.add("You feel rested.", WHITE);
+        tcod.activity = None;
+        return PlayerAction::DidnTakeTurn;
+    }
+    PlayerAction::TookTurn
+}
+
+fn open_neighbours(x: i32, y: i32, map: &Map) -> usize {
+    adjacent_tiles(x, y)
+        .iter()
+        .filter(|&&(x, y)| !map[x as usize][y as usize].blocked)
+        .count()
+}
+
+fn run(
+    dx: i32,
+    dy: i32,
+    tcod: &mut Tcod,
+    objects: &mut Vec<Object>,
+    game: &mut Game,
+) -> PlayerAction {
+    let (x, y) = objects[PLAYER].pos();
+    let (next_x, next_y) = (x + dx, y + dy);
+    let next_tile = &game.map[next_x as usize][next_y as usize];
+    if is_blocked(next_x, next_y, &game.map, objects)
+        || next_tile.terrain.is_hazard()
+        || is_known_trap(next_x, next_y, &game.map)
+    {
+        tcod.activity = None;
+        return PlayerAction::DidnTakeTurn;
+    }
+
+    let openness = open_neighbours(x, y, &game.map);
+    if !player_move_or_attack(dx, dy, tcod, objects, game) {
+        tcod.activity = None;
+        return PlayerAction::DidnTakeTurn;
+    }
+    let (x, y) = objects[PLAYER].pos();
+    let on_something = objects
+        .iter()
+        .any(|object| object.pos() == (x, y) && (object.item.is_some() || object.name == "stairs"));
+    let door_nearby = adjacent_tiles(x, y).iter().any(|&(door_x, door_y)| {
+        let ahead = (door_x - x) * dx + (door_y - y) * dy >= 0;
+        ahead && game.map[door_x as usize][door_y as usize].door.is_some()
+    });
+    if on_something || door_nearby || open_neighbours(x, y, &game.map) != openness {
+        tcod.activity = None;
     }
+    PlayerAction::TookTurn
 }
 
 fn msgbox(text: &str, width: i32, root: &mut Root) {
@@ -1660,6 +5235,136 @@ fn msgbox(text: &str, width: i32, root: &mut Root) {
     menu(text, options, width, root);
 }
 
+fn read_line(prompt: &str, root: &mut Root) -> Option<String> {
+    let mut text = String::new();
+    loop {
+        let mut window = Offscreen::new(SCREEN_WIDTH, 1);
+        window.set_default_foreground(WHITE);
+        window.print_ex(
+            0,
+            0,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            format!("{}{}_", prompt, text),
+        );
+        blit(
+            &window,
+            (0, 0),
+            (SCREEN_WIDTH, 1),
+            root,
+            (0, SCREEN_HEIGHT - 1),
+            1.0,
+            1.0,
+        );
+        root.flush();
+
+        let key = root.wait_for_keypress(true);
+        match key.code {
+            Enter => return Some(text),
+            Escape => return None,
+            Backspace => {
+                text.pop();
+            }
+            _ if key.printable.is_ascii_graphic() || key.printable == ' ' => {
+                text.push(key.printable);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn message_history(log: &Messages, root: &mut Root) {
+    let page_height = (SCREEN_HEIGHT - 3) as usize;
+    let mut offset = 0;
+    let mut search: Option<String> = None;
+    loop {
+        let messages: Vec<_> = log
+            .iter()
+            .filter(|message| {
+                search.as_ref().is_none_or(|search| {
+                    message.text.to_lowercase().contains(&search.to_lowercase())
+                })
+            })
+            .collect();
+        offset = cmp::min(offset, messages.len().saturating_sub(1));
+
+        let mut window = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+        window.set_default_foreground(WHITE);
+        window.print_ex(
+            0,
+            0,
+            BackgroundFlag::None,
+            TextAlignment::Left,
+            "Message history: arrows and PageUp/PageDown scroll, '/' searches, Escape closes.",
+        );
+        let status = match search {
+            Some(ref search) => format!("{} messages matching \"{}\"", messages.len(), search),
+            None => format!("{} messages", messages.len()),
+        };
+        window.set_default_foreground(LIGHT_GREY);
+        window.print_ex(0, 1, BackgroundFlag::None, TextAlignment::Left, status);
+
+        let mut y = SCREEN_HEIGHT;
+        let mut shown = messages.iter().rev().skip(offset).peekable();
+        while let Some(message) = shown.next() {
+            let msg = message.text();
+            let msg_height = window.get_height_rect(0, 0, SCREEN_WIDTH, 0, &msg);
+            y -= msg_height;
+            if y < 3 {
+                break;
+            }
+            window.set_default_foreground(message.color);
+            window.print_rect(0, y, SCREEN_WIDTH, 0, &msg);
+            let turn_starts = shown
+                .peek()
+                .is_none_or(|previous| previous.turn != message.turn);
+            if turn_starts {
+                y -= 1;
+                if y < 3 {
+                    break;
+                }
+                window.set_default_foreground(DARK_GREY);
+                window.print_ex(
+                    0,
+                    y,
+                    BackgroundFlag::None,
+                    TextAlignment::Left,
+                    format!("-- turn {} --", message.turn),
+                );
+            }
+        }
+
+        blit(
+            &window,
+            (0, 0),
+            (SCREEN_WIDTH, SCREEN_HEIGHT),
+            root,
+            (0, 0),
+            1.0,
+            1.0,
+        );
+        root.flush();
+
+        let key = root.wait_for_keypress(true);
+        match key {
+            Key { code: Up, .. } | Key { code: NumPad8, .. } => offset += 1,
+            Key { code: Down, .. } | Key { code: NumPad2, .. } => offset = offset.saturating_sub(1),
+            Key { code: PageUp, .. } | Key { code: NumPad9, .. } => offset += page_height,
+            Key { code: PageDown, .. } | Key { code: NumPad3, .. } => {
+                offset = offset.saturating_sub(page_height)
+            }
+            Key { printable: '/', .. } => {
+                if let Some(text) = read_line("Search: ", root) {
+                    search = if text.is_empty() { None } else { Some(text) };
+                    offset = 0;
+                }
+            }
+            Key { code: Escape, .. } => break,
+            _ => {}
+        }
+    }
+}
+
 fn main_menu(tcod: &mut Tcod) {
     let img = tcod::image::Image::from_file("menu_background.png")
         .ok()
@@ -1697,8 +5402,16 @@ fn main_menu(tcod: &mut Tcod) {
                     initialise_fov(&game.map, tcod);
                     play_game(&mut objects, &mut game, tcod);
                 }
-                Err(_e) => {
-                    msgbox("\nNo saved game to load.\n", 24, &mut tcod.root);
+                Err(e) => {
+                    let missing = e
+                        .downcast_ref::<io::Error>()
+                        .is_some_and(|e| e.kind() == io::ErrorKind::NotFound);
+                    if missing {
+                        msgbox("\nNo saved game to load.\n", 24, &mut tcod.root);
+                    } else {
+                        let msg = format!("\nCould not load the saved game: {}\n", e);
+                        msgbox(&msg, INVENTORY_WIDTH, &mut tcod.root);
+                    }
                     continue;
                 }
             },
@@ -1710,19 +5423,40 @@ fn main_menu(tcod: &mut Tcod) {
     }
 }
 
-fn save_game(objects: &[Object], game: &Game) -> Result<(), Box<Error>> {
+fn save_game(objects: &[Object], game: &Game) -> Result<(), Box<dyn Error>> {
     let save_data = serde_json::to_string(&(objects, game))?;
     let mut file = File::create("savegame")?;
     file.write_all(save_data.as_bytes())?;
     Ok(())
 }
 
-fn load_game() -> Result<(Vec<Object>, Game), Box<Error>> {
+fn load_game() -> Result<(Vec<Object>, Game), Box<dyn Error>> {
     let mut json_save_state = String::new();
     let mut file = File::open("savegame")?;
     file.read_to_string(&mut json_save_state)?;
-    let result = serde_json::from_str::<(Vec<Object>, Game)>(&json_save_state)?;
-    Ok(result)
+    let (objects, mut game) = serde_json::from_str::<(Vec<Object>, Game)>(&json_save_state)?;
+    if game.map.len() > MAP_WIDTH as usize
+        || game
+            .map
+            .iter()
+            .any(|column| column.len() > MAP_HEIGHT as usize)
+    {
+        return Err(Box::new(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the saved map is larger than this version supports",
+        )));
+    }
+    for column in game.map.iter_mut() {
+        for tile in column.iter_mut() {
+            if tile.blocked && tile.door.is_none() {
+                tile.terrain = Terrain::Wall;
+            }
+        }
+        column.resize(MAP_HEIGHT as usize, Tile::wall());
+    }
+    game.map
+        .resize(MAP_WIDTH as usize, vec![Tile::wall(); MAP_HEIGHT as usize]);
+    Ok((objects, game))
 }
 
 fn next_level(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
@@ -1737,13 +5471,18 @@ fn next_level(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
          the heart of the dungeon...",
         RED,
     );
+    descend(tcod, objects, game);
+}
+
+fn descend(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
     game.dungeon_level += 1;
     game.map = make_map(objects, game.dungeon_level);
+    game.remembered_items.clear();
     initialise_fov(&game.map, tcod);
 }
 
 fn main() {
-    let root = Root::initializer()
+    let mut root = Root::initializer()
         .font("terminal8x8_gs_tc.png", FontLayout::Tcod)
         .font_type(FontType::Greyscale)
         .size(SCREEN_WIDTH, SCREEN_HEIGHT)
@@ -1751,13 +5490,55 @@ fn main() {
         .init();
     tcod::system::set_fps(LIMIT_FPS);
 
+    let keymap = match load_keymap() {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            let missing = e
+                .downcast_ref::<io::Error>()
+                .is_some_and(|e| e.kind() == io::ErrorKind::NotFound);
+            if !missing {
+                let msg = format!(
+                    "\nCould not read {}: {}\nUsing the default key bindings.\n",
+                    KEYMAP_FILE, e
+                );
+                msgbox(&msg, INVENTORY_WIDTH, &mut root);
+            }
+            Keymap::new(KeyPreset::Numpad)
+        }
+    };
+
     let mut tcod = Tcod {
         root,
         con: Offscreen::new(MAP_WIDTH, MAP_HEIGHT),
         panel: Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT),
         fov: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
         mouse: Default::default(),
+        camera: (0, 0),
+        cursor: None,
+        keymap,
+        activity: None,
+        count: None,
     };
 
     main_menu(&mut tcod);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_preset_binds_every_command() {
+        for &preset in &[KeyPreset::Numpad, KeyPreset::Vi, KeyPreset::Wasd] {
+            let keymap = Keymap::new(preset);
+            for &command in Command::all() {
+                assert!(
+                    !keymap.keys(command).is_empty(),
+                    "{:?} has no key in the {:?} preset",
+                    command,
+                    preset
+                );
+            }
+        }
+    }
+}